@@ -1,9 +1,48 @@
+use std::collections::HashMap;
+
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Title {
     pub romaji: Option<String>,
+    pub english: Option<String>,
+    pub native: Option<String>,
+}
+
+// Any part can be missing, eg. only the year is known for an announced anime
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FuzzyDate {
+    pub year: Option<i32>,
+    pub month: Option<u32>,
+    pub day: Option<u32>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MediaTag {
+    pub name: String,
+    pub rank: Option<u32>,
+    pub is_media_spoiler: Option<bool>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Studios {
+    pub edges: Vec<StudioEdge>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StudioEdge {
+    pub is_main: bool,
+    pub node: StudioNode,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StudioNode {
+    pub name: String,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -23,6 +62,9 @@ pub struct RelationsEdge {
 #[serde(rename_all = "camelCase")]
 pub struct RelationsNode {
     pub id_mal: Option<i32>,
+    // ANIME or MANGA, manga have their own MAL ids
+    #[serde(rename = "type")]
+    pub media_type: Option<String>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -34,20 +76,39 @@ pub struct AnilistErrorLocation {
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct AnilistError {
     pub message: String,
-    pub status: i32,
+    pub status: Option<i32>,
+    #[serde(default)]
     pub locations: Vec<AnilistErrorLocation>,
+    // Alias of the anime followed by the fields leading to the error
+    #[serde(default)]
+    pub path: Vec<Value>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AnilistResponse {
-    pub data: AnilistData,
+    // Keyed by the alias each anime was requested with
+    pub data: Option<HashMap<String, Option<AniListAnimeItem>>>,
     pub errors: Option<Vec<AnilistError>>,
 }
 
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AiringSchedule {
+    pub nodes: Vec<AiringScheduleNode>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AiringScheduleNode {
+    pub episode: u32,
+    // Unix timestamp
+    pub airing_at: i64,
+}
+
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct CoverImage {
-    pub large: String,
+    pub large: Option<String>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -56,48 +117,23 @@ pub struct AniListAnimeItem {
     pub status: String,
     pub relations: Option<Relations>,
     pub title: Title,
+    pub synonyms: Option<Vec<String>>,
     pub id_mal: Option<u32>,
+    pub format: Option<String>,
     pub season: Option<String>,
     pub season_year: Option<u32>,
-    pub cover_image: CoverImage,
-}
-
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct AnilistData {
-    pub anime1: Option<AniListAnimeItem>,
-    pub anime2: Option<AniListAnimeItem>,
-    pub anime3: Option<AniListAnimeItem>,
-    pub anime4: Option<AniListAnimeItem>,
-    pub anime5: Option<AniListAnimeItem>,
-    pub anime6: Option<AniListAnimeItem>,
-    pub anime7: Option<AniListAnimeItem>,
-    pub anime8: Option<AniListAnimeItem>,
-    pub anime9: Option<AniListAnimeItem>,
-    pub anime10: Option<AniListAnimeItem>,
-    pub anime11: Option<AniListAnimeItem>,
-    pub anime12: Option<AniListAnimeItem>,
-    pub anime13: Option<AniListAnimeItem>,
-    pub anime14: Option<AniListAnimeItem>,
-    pub anime15: Option<AniListAnimeItem>,
-    pub anime16: Option<AniListAnimeItem>,
-    pub anime17: Option<AniListAnimeItem>,
-    pub anime18: Option<AniListAnimeItem>,
-    pub anime19: Option<AniListAnimeItem>,
-    pub anime20: Option<AniListAnimeItem>,
-    pub anime21: Option<AniListAnimeItem>,
-    pub anime22: Option<AniListAnimeItem>,
-    pub anime23: Option<AniListAnimeItem>,
-    pub anime24: Option<AniListAnimeItem>,
-    pub anime25: Option<AniListAnimeItem>,
-    pub anime26: Option<AniListAnimeItem>,
-    pub anime27: Option<AniListAnimeItem>,
-    pub anime28: Option<AniListAnimeItem>,
-    pub anime29: Option<AniListAnimeItem>,
-    pub anime30: Option<AniListAnimeItem>,
-    pub anime31: Option<AniListAnimeItem>,
-    pub anime32: Option<AniListAnimeItem>,
-    pub anime33: Option<AniListAnimeItem>,
-    pub anime34: Option<AniListAnimeItem>,
-    pub anime35: Option<AniListAnimeItem>,
+    pub start_date: Option<FuzzyDate>,
+    pub end_date: Option<FuzzyDate>,
+    pub episodes: Option<u32>,
+    pub duration: Option<u32>,
+    pub airing_schedule: Option<AiringSchedule>,
+    pub genres: Option<Vec<String>>,
+    pub tags: Option<Vec<MediaTag>>,
+    pub studios: Option<Studios>,
+    pub average_score: Option<u32>,
+    pub popularity: Option<u32>,
+    pub cover_image: Option<CoverImage>,
+    pub banner_image: Option<String>,
+    pub description: Option<String>,
+    pub is_adult: Option<bool>,
 }
@@ -1,24 +1,62 @@
 pub mod api_types;
+pub mod provider;
+pub mod rate_limit;
 
 use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
 
-use reqwest::{Client, Response};
+use anyhow::anyhow;
+use chrono::Utc;
+use lazy_static::lazy_static;
+use reqwest::{Client, Response, StatusCode};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
 use self::api_types::{AniListAnimeItem, AnilistResponse};
+use self::rate_limit::{RateLimiter, DEFAULT_ANILIST_RATE_LIMIT};
+use crate::metrics;
 
-fn get_header_i32(response: &Response, key: String, default: i32) -> i32 {
-    let default_str: String = default.to_string().to_owned();
-    let default_str = default_str.as_str();
+lazy_static! {
+    // Shared by every request to anilist, ANILIST_RATE_LIMIT sets the
+    // requests per minute until anilist tells us its actual limit
+    static ref RATE_LIMITER: RateLimiter = RateLimiter::new(
+        std::env::var("ANILIST_RATE_LIMIT")
+            .ok()
+            .and_then(|limit| limit.parse().ok())
+            .filter(|limit| *limit > 0)
+            .unwrap_or(DEFAULT_ANILIST_RATE_LIMIT)
+    );
+}
+
+// Used when a 429 does not say how long to wait
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+fn get_header_u64(response: &Response, key: &str) -> Option<u64> {
     response
         .headers()
-        .get(key)
-        .unwrap_or(&reqwest::header::HeaderValue::from_str(default_str).unwrap())
+        .get(key)?
         .to_str()
-        .unwrap_or(default_str)
-        .parse::<i32>()
-        .unwrap_or(default)
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+}
+
+// How long anilist wants us to wait after a 429. retry-after is preferred
+// since x-ratelimit-reset is a timestamp and depends on our clock being right
+fn get_retry_after(response: &Response) -> Duration {
+    if let Some(seconds) = get_header_u64(response, "retry-after") {
+        return Duration::from_secs(seconds);
+    }
+
+    if let Some(reset) = get_header_u64(response, "x-ratelimit-reset") {
+        let seconds = reset as i64 - Utc::now().timestamp();
+        if seconds > 0 {
+            return Duration::from_secs(seconds as u64);
+        }
+    }
+
+    DEFAULT_RETRY_AFTER
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -37,25 +75,30 @@ impl Display for GqlQuery {
 pub struct AniListResult {
     pub response: Result<AnilistResponse, anyhow::Error>,
     pub query: GqlQuery,
-    // pub rate_limit_remaining: i32,
-    // pub rate_limit_current: i32,
-    pub rate_limit_reset: i32,
-    // pub retry_after: i32,
+    // Set when anilist rate limited the request
+    pub retry_after: Option<Duration>,
 }
 
-pub async fn get_animes_from_anilist(reqwest: &Client, ids: Vec<u32>) -> AniListResult {
+pub async fn get_animes_from_anilist(reqwest: &Client, url: &str, ids: Vec<u32>) -> AniListResult {
     let query = generate_gql_query(ids);
-    get_animes_from_anilist_gql(reqwest, query).await
+    get_animes_from_anilist_gql(reqwest, url, query).await
 }
 
-pub async fn get_animes_from_anilist_gql(reqwest: &Client, gql_query: GqlQuery) -> AniListResult {
+pub async fn get_animes_from_anilist_gql(
+    reqwest: &Client,
+    url: &str,
+    gql_query: GqlQuery,
+) -> AniListResult {
     tracing::trace!("GQL Query: {:?}", gql_query);
 
-    let res = reqwest
-        .post("https://graphql.anilist.co")
-        .json(&json!(gql_query))
-        .send()
-        .await;
+    RATE_LIMITER.acquire().await;
+    let started = Instant::now();
+    let res = reqwest.post(url).json(&json!(gql_query)).send().await;
+    metrics::record_upstream(
+        "anilist",
+        res.as_ref().ok().map(|res| res.status().as_u16()),
+        started,
+    );
 
     let res = match res {
         Ok(res) => res,
@@ -64,27 +107,42 @@ pub async fn get_animes_from_anilist_gql(reqwest: &Client, gql_query: GqlQuery)
             return AniListResult {
                 response: Err(anyhow::Error::new(e)),
                 query: gql_query,
-                // rate_limit_current: -1,
-                // rate_limit_remaining: -1,
-                rate_limit_reset: -1,
-                // retry_after: -1,
+                retry_after: None,
             };
         }
     };
 
-    let rate_limit_remaining = get_header_i32(&res, "x-ratelimit-remaining".to_string(), -1);
-    let rate_limit_current = get_header_i32(&res, "x-ratelimit-limit".to_string(), -1);
-    let rate_limit_reset = get_header_i32(&res, "x-ratelimit-reset".to_string(), -1);
-    let retry_after = get_header_i32(&res, "retry-after".to_string(), -1);
-
-    tracing::debug!(
-        retry_after,
-        rate_limit_reset,
-        rate_limit_current,
-        rate_limit_remaining
+    let rate_limit = get_header_u64(&res, "x-ratelimit-limit");
+    let rate_limit_remaining = get_header_u64(&res, "x-ratelimit-remaining");
+    tracing::debug!(?rate_limit, ?rate_limit_remaining);
+    RATE_LIMITER.update(
+        rate_limit.map(|limit| limit as u32),
+        rate_limit_remaining.map(|remaining| remaining as u32),
     );
 
-    let text = res.text().await.unwrap();
+    if res.status() == StatusCode::TOO_MANY_REQUESTS {
+        let retry_after = get_retry_after(&res);
+        tracing::warn!("Rate limited by Anilist, retrying after {:?}", retry_after);
+        RATE_LIMITER.block_for(retry_after);
+
+        return AniListResult {
+            response: Err(anyhow!("Rate limited by Anilist")),
+            query: gql_query,
+            retry_after: Some(retry_after),
+        };
+    }
+
+    let text = match res.text().await {
+        Ok(text) => text,
+        Err(e) => {
+            tracing::error!("Failed to read Anilist response: {}", e);
+            return AniListResult {
+                response: Err(anyhow::Error::new(e)),
+                query: gql_query,
+                retry_after: None,
+            };
+        }
+    };
     let anime: Result<AnilistResponse, serde_json::Error> = serde_json::from_str(&text);
     let anime = match anime {
         Ok(json) => Ok(json),
@@ -98,39 +156,43 @@ pub async fn get_animes_from_anilist_gql(reqwest: &Client, gql_query: GqlQuery)
     AniListResult {
         query: gql_query,
         response: anime,
-        // rate_limit_current,
-        // rate_limit_remaining,
-        rate_limit_reset,
-        // retry_after,
+        retry_after: None,
     }
 }
 
-// max this can be is 17, if higher is needed, need to change the queue processor
-// to ensure it can grab the data from the query response
-pub const MAX_ANILIST_PER_QUERY: usize = 35;
+// Default number of animes requested in a single query, can be changed
+// with ANILIST_BATCH_SIZE. Every anime adds to the query complexity and
+// anilist rejects the whole query once it goes over its limit
+pub const DEFAULT_ANILIST_PER_QUERY: usize = 35;
 
-pub fn generate_gql_query(ids: Vec<u32>) -> GqlQuery {
-    let mut ids = ids;
-    if ids.len() > MAX_ANILIST_PER_QUERY {
-        tracing::error!("Too many ids: {}", ids.len());
-        ids.truncate(MAX_ANILIST_PER_QUERY);
-    }
+// Name of the alias and variable used for the anime at index `i` of a query
+pub fn alias(i: usize) -> String {
+    format!("anime{}", i + 1)
+}
 
+// Inverse of `alias`
+pub fn alias_index(alias: &str) -> Option<usize> {
+    alias
+        .strip_prefix("anime")?
+        .parse::<usize>()
+        .ok()?
+        .checked_sub(1)
+}
+
+pub fn generate_gql_query(ids: Vec<u32>) -> GqlQuery {
     let mut query = "query media(".to_owned();
     let mut variables = json!({});
 
     for (i, id) in ids.iter().enumerate() {
-        let i = i + 1;
-        query.push_str(&format!("$anime{}: Int,", i));
-        let variable_name = "anime".to_owned() + &i.to_string();
-        variables[variable_name] = json!(id);
+        query.push_str(&format!("${}: Int,", alias(i)));
+        variables[alias(i)] = json!(id);
     }
 
     query.push_str(") {");
 
     let media_selection = String::from(ANILIST_MEDIA_SELECTION);
-    for i in 1..ids.len() + 1 {
-        let media_selection = media_selection.replace("{}", &i.to_string());
+    for i in 0..ids.len() {
+        let media_selection = media_selection.replace("{}", &alias(i));
         query.push_str(&media_selection);
     }
 
@@ -140,112 +202,73 @@ pub fn generate_gql_query(ids: Vec<u32>) -> GqlQuery {
 }
 
 const ANILIST_MEDIA_SELECTION: &str = r#"
-anime{}: Media(idMal: $anime{}, type: ANIME) {
+{}: Media(idMal: ${}, type: ANIME) {
     status
     idMal
     title {
       romaji
+      english
+      native
     }
+    synonyms
+    format
     season
     seasonYear
+    startDate {
+      year
+      month
+      day
+    }
+    endDate {
+      year
+      month
+      day
+    }
+    episodes
+    duration
+    airingSchedule(notYetAired: true, perPage: 25) {
+      nodes {
+        episode
+        airingAt
+      }
+    }
+    genres
+    tags {
+      name
+      rank
+      isMediaSpoiler
+    }
+    studios {
+      edges {
+        isMain
+        node {
+          name
+        }
+      }
+    }
+    averageScore
+    popularity
     coverImage {
       large
     }
+    bannerImage
+    description(asHtml: false)
+    isAdult
     relations {
       edges {
         relationType(version: 2)
         node {
           idMal
+          type
         }
       }
     }
   }
 "#;
 
-pub fn lines_per_query() -> usize {
-    ANILIST_MEDIA_SELECTION.split('\n').count()
-}
-
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct AnilistItems {
-    pub anime1: Option<AniListAnimeItem>,
-    pub anime2: Option<AniListAnimeItem>,
-    pub anime3: Option<AniListAnimeItem>,
-    pub anime4: Option<AniListAnimeItem>,
-    pub anime5: Option<AniListAnimeItem>,
-    pub anime6: Option<AniListAnimeItem>,
-    pub anime7: Option<AniListAnimeItem>,
-    pub anime8: Option<AniListAnimeItem>,
-    pub anime9: Option<AniListAnimeItem>,
-    pub anime10: Option<AniListAnimeItem>,
-    pub anime11: Option<AniListAnimeItem>,
-    pub anime12: Option<AniListAnimeItem>,
-    pub anime13: Option<AniListAnimeItem>,
-    pub anime14: Option<AniListAnimeItem>,
-    pub anime15: Option<AniListAnimeItem>,
-    pub anime16: Option<AniListAnimeItem>,
-    pub anime17: Option<AniListAnimeItem>,
-    pub anime18: Option<AniListAnimeItem>,
-    pub anime19: Option<AniListAnimeItem>,
-    pub anime20: Option<AniListAnimeItem>,
-    pub anime21: Option<AniListAnimeItem>,
-    pub anime22: Option<AniListAnimeItem>,
-    pub anime23: Option<AniListAnimeItem>,
-    pub anime24: Option<AniListAnimeItem>,
-    pub anime25: Option<AniListAnimeItem>,
-    pub anime26: Option<AniListAnimeItem>,
-    pub anime27: Option<AniListAnimeItem>,
-    pub anime28: Option<AniListAnimeItem>,
-    pub anime29: Option<AniListAnimeItem>,
-    pub anime30: Option<AniListAnimeItem>,
-    pub anime31: Option<AniListAnimeItem>,
-    pub anime32: Option<AniListAnimeItem>,
-    pub anime33: Option<AniListAnimeItem>,
-    pub anime34: Option<AniListAnimeItem>,
-    pub anime35: Option<AniListAnimeItem>,
-}
-
 pub fn get_anime_from_anilist_result(
-    result: AnilistResponse,
+    result: &mut AnilistResponse,
     i: usize,
 ) -> Option<AniListAnimeItem> {
-    match i {
-        0 => result.data.anime1,
-        1 => result.data.anime2,
-        2 => result.data.anime3,
-        3 => result.data.anime4,
-        4 => result.data.anime5,
-        5 => result.data.anime6,
-        6 => result.data.anime7,
-        7 => result.data.anime8,
-        8 => result.data.anime9,
-        9 => result.data.anime10,
-        10 => result.data.anime11,
-        11 => result.data.anime12,
-        12 => result.data.anime13,
-        13 => result.data.anime14,
-        14 => result.data.anime15,
-        15 => result.data.anime16,
-        16 => result.data.anime17,
-        17 => result.data.anime18,
-        18 => result.data.anime19,
-        19 => result.data.anime20,
-        20 => result.data.anime21,
-        21 => result.data.anime22,
-        22 => result.data.anime23,
-        23 => result.data.anime24,
-        24 => result.data.anime25,
-        25 => result.data.anime26,
-        26 => result.data.anime27,
-        27 => result.data.anime28,
-        28 => result.data.anime29,
-        29 => result.data.anime30,
-        30 => result.data.anime31,
-        31 => result.data.anime32,
-        32 => result.data.anime33,
-        33 => result.data.anime34,
-        34 => result.data.anime35,
-        _ => None,
-    }
+    result.data.as_mut()?.remove(&alias(i))?
 }
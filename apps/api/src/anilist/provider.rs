@@ -0,0 +1,212 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use reqwest::Client;
+
+use super::api_types::{AniListAnimeItem, FuzzyDate};
+use super::{alias_index, get_anime_from_anilist_result, get_animes_from_anilist};
+use crate::metadata::{
+    AiringEpisode, AnimeMetadata, AnimeRelation, AnimeStudio, AnimeTag, MetadataBatch,
+    MetadataError, MetadataProvider,
+};
+
+pub struct AniListProvider {
+    reqwest: Client,
+    url: String,
+    // Lowered when anilist rejects a query for being too complex
+    batch_size: AtomicUsize,
+}
+
+impl AniListProvider {
+    pub fn new(reqwest: Client, url: String, batch_size: usize) -> Self {
+        AniListProvider {
+            reqwest,
+            url,
+            batch_size: AtomicUsize::new(batch_size.max(1)),
+        }
+    }
+}
+
+#[async_trait]
+impl MetadataProvider for AniListProvider {
+    fn name(&self) -> &'static str {
+        "AniList"
+    }
+
+    fn batch_size(&self) -> usize {
+        self.batch_size.load(Ordering::Relaxed)
+    }
+
+    async fn fetch(&self, ids: Vec<u32>) -> Result<MetadataBatch, MetadataError> {
+        let animes = get_animes_from_anilist(&self.reqwest, &self.url, ids.clone()).await;
+
+        if let Some(retry_after) = animes.retry_after {
+            let retry_after =
+                chrono::Duration::from_std(retry_after).unwrap_or(chrono::Duration::minutes(1));
+            return Err(MetadataError::RateLimited(Utc::now() + retry_after));
+        }
+
+        let mut anilist_response = match animes.response {
+            Ok(response) => response,
+            Err(err) if err.is::<serde_json::Error>() => return Err(MetadataError::Parse(err)),
+            Err(err) => return Err(MetadataError::Request(err)),
+        };
+
+        let mut batch = MetadataBatch::default();
+        if let Some(errors) = anilist_response.errors.take() {
+            if let Some(error) = errors
+                .iter()
+                .find(|error| error.message.to_lowercase().contains("complexity"))
+            {
+                let batch_size = (ids.len() / 2).max(1);
+                self.batch_size.store(batch_size, Ordering::Relaxed);
+                tracing::warn!(
+                    "Query for {} animes was too complex, lowering batch size to {}",
+                    ids.len(),
+                    batch_size
+                );
+
+                return Err(MetadataError::TooLarge(anyhow!(error.message.clone())));
+            }
+
+            tracing::debug!("{}", animes.query);
+
+            for error in errors {
+                // The first segment of the path is the alias of the anime the error is for
+                let anime_id = error
+                    .path
+                    .first()
+                    .and_then(|segment| segment.as_str())
+                    .and_then(alias_index)
+                    .and_then(|index| ids.get(index))
+                    .copied();
+
+                match (anime_id, error.status) {
+                    (Some(anime_id), Some(404)) => {
+                        tracing::error!("Anime {:?} has not found on anilist", anime_id);
+                        batch.not_found.push(anime_id);
+                    }
+                    // Not returned, so the importer backs it off and retries it
+                    (Some(anime_id), _) => {
+                        tracing::error!(anime = anime_id, "Anilist error: {}", error.message)
+                    }
+                    (None, _) => tracing::error!("Anilist error: {}", error.message),
+                }
+            }
+        }
+
+        tracing::info!("Got animes from anilist");
+
+        for anime_index in 0..ids.len() {
+            let anime = get_anime_from_anilist_result(&mut anilist_response, anime_index);
+
+            if let Some(anime) = anime.and_then(into_metadata) {
+                batch.animes.push(anime);
+            }
+        }
+
+        Ok(batch)
+    }
+}
+
+fn into_metadata(anime: AniListAnimeItem) -> Option<AnimeMetadata> {
+    let id_mal = anime.id_mal?;
+
+    let relations = anime
+        .relations
+        .map(|relations| relations.edges)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|relation| relation.node.media_type.as_deref() == Some("ANIME"))
+        .filter_map(|relation| match relation.node.id_mal {
+            Some(related_id) => Some(AnimeRelation {
+                id_mal: related_id as u32,
+                relation_type: relation.relation_type,
+            }),
+            None => {
+                tracing::debug!(anime = id_mal, "Relation had no mal id");
+                None
+            }
+        })
+        .collect();
+
+    let tags = anime
+        .tags
+        .unwrap_or_default()
+        .into_iter()
+        .map(|tag| AnimeTag {
+            name: tag.name,
+            rank: tag.rank.unwrap_or_default(),
+            is_spoiler: tag.is_media_spoiler.unwrap_or_default(),
+        })
+        .collect();
+
+    let airing = anime
+        .airing_schedule
+        .map(|schedule| schedule.nodes)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|node| {
+            Some(AiringEpisode {
+                episode: node.episode,
+                airing_at: DateTime::from_timestamp(node.airing_at, 0)?,
+            })
+        })
+        .collect();
+
+    // The same studio can be listed twice, once as the main studio
+    let mut studios: Vec<AnimeStudio> = vec![];
+    for edge in anime
+        .studios
+        .map(|studios| studios.edges)
+        .unwrap_or_default()
+    {
+        match studios
+            .iter_mut()
+            .find(|studio| studio.name == edge.node.name)
+        {
+            Some(studio) => studio.is_main |= edge.is_main,
+            None => studios.push(AnimeStudio {
+                name: edge.node.name,
+                is_main: edge.is_main,
+            }),
+        }
+    }
+
+    Some(AnimeMetadata {
+        id_mal,
+        romaji_title: anime.title.romaji,
+        english_title: anime.title.english,
+        native_title: anime.title.native,
+        synonyms: anime.synonyms.unwrap_or_default(),
+        status: anime.status,
+        format: anime.format,
+        picture: anime
+            .cover_image
+            .and_then(|image| image.large)
+            .unwrap_or_default(),
+        banner_image: anime.banner_image,
+        description: anime.description,
+        season: anime.season,
+        season_year: anime.season_year,
+        start_date: anime.start_date.and_then(to_date),
+        end_date: anime.end_date.and_then(to_date),
+        episodes: anime.episodes,
+        duration: anime.duration,
+        airing,
+        genres: anime.genres.unwrap_or_default(),
+        tags,
+        studios,
+        average_score: anime.average_score,
+        popularity: anime.popularity,
+        is_adult: anime.is_adult.unwrap_or_default(),
+        relations,
+    })
+}
+
+// Dates missing the month or day are not stored rather than guessed
+fn to_date(date: FuzzyDate) -> Option<NaiveDate> {
+    NaiveDate::from_ymd_opt(date.year?, date.month?, date.day?)
+}
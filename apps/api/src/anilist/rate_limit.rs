@@ -0,0 +1,191 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::metrics;
+
+// Requests per minute anilist allows when it does not tell us otherwise
+pub const DEFAULT_ANILIST_RATE_LIMIT: u32 = 90;
+
+// Token bucket shared by every request to anilist. Tokens refill evenly over a
+// minute and the bucket is corrected from the rate limit headers of each response.
+// Uses `Instant` so changes to the system clock do not affect it
+pub struct RateLimiter {
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    // Requests allowed per minute
+    limit: u32,
+    tokens: f64,
+    last_refill: Instant,
+    // Set after a 429, nothing is sent until then
+    blocked_until: Option<Instant>,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        let per_second = self.limit as f64 / 60.0;
+        self.tokens = (self.tokens + elapsed * per_second).min(self.limit as f64);
+        self.last_refill = now;
+    }
+
+    // How long to wait before a token is available, taking it if there is one
+    fn try_take(&mut self, now: Instant) -> Option<Duration> {
+        if let Some(blocked_until) = self.blocked_until {
+            if blocked_until > now {
+                return Some(blocked_until - now);
+            }
+            self.blocked_until = None;
+        }
+
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return None;
+        }
+
+        let per_second = (self.limit as f64 / 60.0).max(f64::EPSILON);
+        Some(Duration::from_secs_f64((1.0 - self.tokens) / per_second))
+    }
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32) -> Self {
+        let limit = limit.max(1);
+        RateLimiter {
+            bucket: Mutex::new(Bucket {
+                limit,
+                tokens: limit as f64,
+                last_refill: Instant::now(),
+                blocked_until: None,
+            }),
+        }
+    }
+
+    // Waits until a request can be sent without going over the limit
+    pub async fn acquire(&self) {
+        loop {
+            let wait = self.bucket.lock().unwrap().try_take(Instant::now());
+            let Some(wait) = wait else {
+                return;
+            };
+
+            tracing::debug!("Waiting {:?} for anilist rate limit", wait);
+            metrics::inc_counter("rate_limit_sleeps_total", &[("reason", "pacing")]);
+            metrics::inc_counter_by(
+                "rate_limit_sleep_seconds_total",
+                &[("reason", "pacing")],
+                wait.as_secs_f64(),
+            );
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    // Corrects the bucket from the x-ratelimit-limit and x-ratelimit-remaining headers
+    pub fn update(&self, limit: Option<u32>, remaining: Option<u32>) {
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.refill(Instant::now());
+
+        if let Some(limit) = limit.filter(|limit| *limit > 0) {
+            bucket.limit = limit;
+        }
+        // Other clients may share our quota, so anilist's count wins when it is lower
+        if let Some(remaining) = remaining {
+            bucket.tokens = bucket.tokens.min(remaining as f64);
+        }
+    }
+
+    // Stops all requests for `duration`, used when anilist returns a 429
+    pub fn block_for(&self, duration: Duration) {
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = Instant::now();
+        // A silly retry-after should not overflow
+        let until = now
+            .checked_add(duration)
+            .unwrap_or(now + Duration::from_secs(60 * 60));
+        match bucket.blocked_until {
+            Some(current) if current >= until => {}
+            _ => bucket.blocked_until = Some(until),
+        }
+        bucket.tokens = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn try_take_refills_over_time() {
+        // Far enough from process start that subtracting from it cannot underflow
+        let now = Instant::now() + Duration::from_secs(60 * 60);
+
+        // name, tokens, last refill, blocked until, expected wait, tokens left
+        let cases = [
+            ("full bucket", 60.0, now, None, None, 59.0),
+            (
+                "empty bucket waits for one token",
+                0.0,
+                now,
+                None,
+                Some(Duration::from_secs(1)),
+                0.0,
+            ),
+            (
+                "refills a token a second",
+                0.0,
+                now - Duration::from_secs(2),
+                None,
+                None,
+                1.0,
+            ),
+            (
+                "refill is capped at the limit",
+                0.0,
+                now - Duration::from_secs(10 * 60),
+                None,
+                None,
+                59.0,
+            ),
+            (
+                "last refill in the future adds nothing",
+                0.0,
+                now + Duration::from_secs(30),
+                None,
+                Some(Duration::from_secs(1)),
+                0.0,
+            ),
+            (
+                "blocked after a 429",
+                60.0,
+                now,
+                Some(now + Duration::from_secs(5)),
+                Some(Duration::from_secs(5)),
+                60.0,
+            ),
+            (
+                "block that has run out",
+                60.0,
+                now,
+                Some(now - Duration::from_secs(5)),
+                None,
+                59.0,
+            ),
+        ];
+
+        for (name, tokens, last_refill, blocked_until, wait, tokens_left) in cases {
+            let mut bucket = Bucket {
+                limit: 60,
+                tokens,
+                last_refill,
+                blocked_until,
+            };
+
+            assert_eq!(bucket.try_take(now), wait, "{}", name);
+            assert_eq!(bucket.tokens, tokens_left, "{}", name);
+        }
+    }
+}
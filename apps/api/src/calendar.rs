@@ -0,0 +1,82 @@
+// Renders airing schedules as iCalendar (RFC 5545) feeds
+
+use chrono::{DateTime, Utc};
+
+use crate::models::airing_schedules::DBScheduledEpisode;
+
+// Used for animes anilist has no episode duration for
+const DEFAULT_EPISODE_MINUTES: i32 = 24;
+
+// Lines longer than this many bytes must be folded
+const MAX_LINE_LENGTH: usize = 75;
+
+pub fn render_calendar(name: &str, episodes: &[DBScheduledEpisode]) -> String {
+    let now = format_time(Utc::now());
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//sei//airing schedule//EN".to_string(),
+        "CALSCALE:GREGORIAN".to_string(),
+        "METHOD:PUBLISH".to_string(),
+        format!("X-WR-CALNAME:{}", escape(name)),
+        // Hints for how often calendar apps should check for changes
+        "REFRESH-INTERVAL;VALUE=DURATION:PT6H".to_string(),
+        "X-PUBLISHED-TTL:PT6H".to_string(),
+    ];
+
+    for episode in episodes {
+        let summary = format!("{} episode {}", episode.romaji_title, episode.episode);
+        lines.extend([
+            "BEGIN:VEVENT".to_string(),
+            // Stays the same when the episode is delayed so the event is moved
+            format!("UID:{}-{}@sei", episode.anime_id, episode.episode),
+            format!("DTSTAMP:{}", now),
+            format!("DTSTART:{}", format_time(episode.airing_at)),
+            format!(
+                "DURATION:PT{}M",
+                episode.duration.unwrap_or(DEFAULT_EPISODE_MINUTES)
+            ),
+            format!("SUMMARY:{}", escape(&summary)),
+            format!("URL:https://myanimelist.net/anime/{}", episode.anime_id),
+            "END:VEVENT".to_string(),
+        ]);
+    }
+
+    lines.push("END:VCALENDAR".to_string());
+
+    lines
+        .iter()
+        .map(|line| fold(line))
+        .collect::<Vec<_>>()
+        .join("")
+}
+
+fn format_time(time: DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+        .replace('\r', "")
+}
+
+// Splits `line` into lines of at most MAX_LINE_LENGTH bytes, each ending
+// in CRLF. Continuation lines start with a space, which counts towards the limit
+fn fold(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + 2);
+    let mut length = 0;
+    for char in line.chars() {
+        if length + char.len_utf8() > MAX_LINE_LENGTH {
+            folded.push_str("\r\n ");
+            length = 1;
+        }
+        folded.push(char);
+        length += char.len_utf8();
+    }
+    folded.push_str("\r\n");
+
+    folded
+}
@@ -0,0 +1,41 @@
+// Base urls of the services we talk to. They can be changed
+// so the api can be pointed at fakes when testing
+#[derive(Clone, Debug)]
+pub struct UpstreamUrls {
+    pub anilist: String,
+    // MAL REST api, including the version
+    pub mal_api: String,
+    // Prefix of the MAL authorize and token endpoints
+    pub mal_oauth: String,
+    pub jikan: String,
+}
+
+impl Default for UpstreamUrls {
+    fn default() -> Self {
+        UpstreamUrls {
+            anilist: "https://graphql.anilist.co".to_string(),
+            mal_api: "https://api.myanimelist.net/v2".to_string(),
+            mal_oauth: "https://myanimelist.net/v1/oauth2".to_string(),
+            jikan: "https://api.jikan.moe/v4".to_string(),
+        }
+    }
+}
+
+impl UpstreamUrls {
+    // Reads ANILIST_URL, MAL_API_URL, MAL_OAUTH_URL and JIKAN_URL, falling back to the defaults
+    pub fn from_env() -> Self {
+        let default = UpstreamUrls::default();
+        let var = |name: &str, default: String| {
+            std::env::var(name)
+                .map(|url| url.trim_end_matches('/').to_string())
+                .unwrap_or(default)
+        };
+
+        UpstreamUrls {
+            anilist: var("ANILIST_URL", default.anilist),
+            mal_api: var("MAL_API_URL", default.mal_api),
+            mal_oauth: var("MAL_OAUTH_URL", default.mal_oauth),
+            jikan: var("JIKAN_URL", default.jikan),
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use std::time::Duration;
+
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::time;
+
+use super::{AnimeUserEntry, ImportProgress, Importer, ImporterStatus, Lane, ProgressUpdate};
+
+pub enum ImporterCommand {
+    Enqueue(Vec<AnimeUserEntry>),
+    EnqueueAnimeOnly(Vec<u32>, Lane),
+    Cancel(Vec<u32>),
+    Unignore(Vec<u32>),
+    UnignoreAll,
+    RequeueDead(Vec<u32>),
+    RequeueAllDead,
+    Stats(oneshot::Sender<ImporterStatus>),
+    Progress(String, oneshot::Sender<ImportProgress>),
+}
+
+// Cheap to clone handle used to talk to the importer task
+#[derive(Clone)]
+pub struct ImporterHandle {
+    sender: mpsc::UnboundedSender<ImporterCommand>,
+    progress_sender: broadcast::Sender<ProgressUpdate>,
+}
+
+impl ImporterHandle {
+    pub fn add_all(&self, entries: Vec<AnimeUserEntry>) {
+        self.send(ImporterCommand::Enqueue(entries));
+    }
+
+    pub fn add_all_anime_only(&self, ids: Vec<u32>, lane: Lane) {
+        self.send(ImporterCommand::EnqueueAnimeOnly(ids, lane));
+    }
+
+    pub fn cancel(&self, ids: Vec<u32>) {
+        self.send(ImporterCommand::Cancel(ids));
+    }
+
+    pub fn unignore(&self, ids: Vec<u32>) {
+        self.send(ImporterCommand::Unignore(ids));
+    }
+
+    pub fn unignore_all(&self) {
+        self.send(ImporterCommand::UnignoreAll);
+    }
+
+    pub fn requeue_dead(&self, ids: Vec<u32>) {
+        self.send(ImporterCommand::RequeueDead(ids));
+    }
+
+    pub fn requeue_all_dead(&self) {
+        self.send(ImporterCommand::RequeueAllDead);
+    }
+
+    pub async fn stats(&self) -> Option<ImporterStatus> {
+        let (sender, receiver) = oneshot::channel();
+        self.send(ImporterCommand::Stats(sender));
+        receiver.await.ok()
+    }
+
+    pub async fn progress(&self, user_id: String) -> Option<ImportProgress> {
+        let (sender, receiver) = oneshot::channel();
+        self.send(ImporterCommand::Progress(user_id, sender));
+        receiver.await.ok()
+    }
+
+    // Progress updates for every user, sent whenever an entry is queued or finished
+    pub fn subscribe(&self) -> broadcast::Receiver<ProgressUpdate> {
+        self.progress_sender.subscribe()
+    }
+
+    fn send(&self, command: ImporterCommand) {
+        if self.sender.send(command).is_err() {
+            tracing::error!("Importer task is not running, dropping command");
+        }
+    }
+}
+
+impl Importer {
+    // Moves the importer into its own task, it can only be
+    // reached through the returned handle after this
+    pub fn spawn(self) -> ImporterHandle {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let progress_sender = self.progress_sender.clone();
+        tokio::spawn(self.run(receiver));
+
+        ImporterHandle {
+            sender,
+            progress_sender,
+        }
+    }
+
+    async fn run(mut self, mut receiver: mpsc::UnboundedReceiver<ImporterCommand>) {
+        let (batch_sender, mut batch_receiver) = mpsc::unbounded_channel();
+        let mut interval = time::interval(Duration::from_millis(2000));
+
+        loop {
+            tokio::select! {
+                command = receiver.recv() => match command {
+                    Some(command) => self.handle(command).await,
+                    None => break,
+                },
+                Some(batch) = batch_receiver.recv() => self.finish_batch(batch).await,
+                _ = interval.tick() => self.start_batch(&batch_sender).await,
+            }
+        }
+
+        tracing::info!("All importer handles dropped, stopping importer");
+    }
+
+    async fn handle(&mut self, command: ImporterCommand) {
+        match command {
+            ImporterCommand::Enqueue(entries) => self.add_all(entries).await,
+            ImporterCommand::EnqueueAnimeOnly(ids, lane) => {
+                self.add_all_anime_only(ids, lane).await
+            }
+            ImporterCommand::Cancel(ids) => self.cancel(ids).await,
+            ImporterCommand::Unignore(ids) => self.unignore(ids).await,
+            ImporterCommand::UnignoreAll => self.unignore_all().await,
+            ImporterCommand::RequeueDead(ids) => self.requeue_dead(ids).await,
+            ImporterCommand::RequeueAllDead => self.requeue_all_dead().await,
+            ImporterCommand::Stats(sender) => {
+                let _ = sender.send(self.stats());
+            }
+            ImporterCommand::Progress(user_id, sender) => {
+                let _ = sender.send(self.progress(&user_id));
+            }
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::HashSet;
+
+use crate::models::discovered_animes::DiscoverReason;
+
+// Side stories and parents are crawled so movies and
+// OVAs show up next to the main seasons
+pub const DEFAULT_CRAWL_RELATIONS: [&str; 4] = ["PREQUEL", "SEQUEL", "SIDE_STORY", "PARENT"];
+
+// Limits how far the importer follows relations away from animes on a users list
+#[derive(Clone, Debug)]
+pub struct CrawlSettings {
+    // Relation types that also queue the related anime to be imported,
+    // every type is stored either way
+    pub relation_types: HashSet<String>,
+    // Max number of relations between a crawled anime and a users anime
+    pub max_depth: u32,
+    // Max animes crawled from a single users anime
+    pub franchise_cap: usize,
+}
+
+impl Default for CrawlSettings {
+    fn default() -> Self {
+        CrawlSettings {
+            relation_types: DEFAULT_CRAWL_RELATIONS
+                .iter()
+                .map(|relation| relation.to_string())
+                .collect(),
+            max_depth: 2,
+            franchise_cap: 25,
+        }
+    }
+}
+
+impl CrawlSettings {
+    // Reads CRAWL_RELATION_TYPES (comma separated), CRAWL_MAX_DEPTH
+    // and CRAWL_FRANCHISE_CAP, falling back to the defaults
+    pub fn from_env() -> Self {
+        let default = CrawlSettings::default();
+
+        CrawlSettings {
+            relation_types: match std::env::var("CRAWL_RELATION_TYPES") {
+                Ok(types) => types
+                    .split(',')
+                    .map(|relation| relation.trim().to_uppercase())
+                    .filter(|relation| !relation.is_empty())
+                    .collect(),
+                Err(_) => default.relation_types,
+            },
+            max_depth: std::env::var("CRAWL_MAX_DEPTH")
+                .ok()
+                .and_then(|depth| depth.parse().ok())
+                .unwrap_or(default.max_depth),
+            franchise_cap: std::env::var("CRAWL_FRANCHISE_CAP")
+                .ok()
+                .and_then(|cap| cap.parse().ok())
+                .unwrap_or(default.franchise_cap),
+        }
+    }
+
+    // Why a relation of an anime crawled from `origin` should not be crawled, if at all.
+    // `franchise_size` is the number of animes already crawled from the same root
+    pub fn check(&self, origin: CrawlOrigin, franchise_size: usize) -> Option<DiscoverReason> {
+        if origin.depth >= self.max_depth {
+            return Some(DiscoverReason::MaxDepth);
+        }
+
+        if franchise_size >= self.franchise_cap {
+            return Some(DiscoverReason::FranchiseCap);
+        }
+
+        None
+    }
+}
+
+// Where a queued anime was crawled from
+#[derive(Clone, Copy, Debug)]
+pub struct CrawlOrigin {
+    // The users anime the crawl started at
+    pub root: u32,
+    pub depth: u32,
+}
+
+impl CrawlOrigin {
+    pub fn root(id: u32) -> Self {
+        CrawlOrigin { root: id, depth: 0 }
+    }
+
+    pub fn next(self) -> Self {
+        CrawlOrigin {
+            root: self.root,
+            depth: self.depth + 1,
+        }
+    }
+}
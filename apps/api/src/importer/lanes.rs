@@ -0,0 +1,174 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use serde::Serialize;
+
+// Why an anime was queued, earlier lanes have a higher priority
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub enum Lane {
+    // Animes on a users list
+    User,
+    // Animes with stale metadata
+    Refresh,
+    // Animes found through relations of imported animes
+    Relation,
+}
+
+impl Lane {
+    pub const ALL: [Lane; 3] = [Lane::User, Lane::Refresh, Lane::Relation];
+
+    // Max ids taken from the lane per round when building a batch,
+    // lanes with nothing queued give their share to the others
+    fn weight(self) -> usize {
+        match self {
+            Lane::User => 4,
+            Lane::Refresh => 1,
+            Lane::Relation => 2,
+        }
+    }
+}
+
+// Queue order of every lane. Ids are only removed from the front of a lane,
+// anything removed or moved to another lane is skipped when it gets there
+#[derive(Default)]
+pub struct Lanes {
+    order: HashMap<Lane, VecDeque<u32>>,
+    lane_of: HashMap<u32, Lane>,
+}
+
+impl Lanes {
+    // Adds `id` to the back of `lane`, ids already queued in a
+    // lower priority lane are moved up to it
+    pub fn push(&mut self, id: u32, lane: Lane) {
+        if self
+            .lane_of
+            .get(&id)
+            .is_some_and(|current| *current <= lane)
+        {
+            return;
+        }
+
+        self.lane_of.insert(id, lane);
+        self.order.entry(lane).or_default().push_back(id);
+    }
+
+    pub fn remove(&mut self, id: u32) {
+        self.lane_of.remove(&id);
+    }
+
+    pub fn len(&self, lane: Lane) -> usize {
+        self.lane_of
+            .values()
+            .filter(|value| **value == lane)
+            .count()
+    }
+
+    // Takes up to `max` ids without removing them, interleaving the
+    // lanes by weight. Ids rejected by `ready` are skipped
+    pub fn take(&mut self, max: usize, ready: impl Fn(u32) -> bool) -> Vec<u32> {
+        self.drop_stale();
+
+        let mut iters: Vec<(Lane, std::collections::vec_deque::Iter<u32>)> = Lane::ALL
+            .iter()
+            .filter_map(|lane| self.order.get(lane).map(|order| (*lane, order.iter())))
+            .collect();
+
+        let mut ids = vec![];
+        // The same id can be in a lane twice if it was removed and added again
+        let mut seen = HashSet::new();
+        while ids.len() < max && !iters.is_empty() {
+            iters.retain_mut(|(lane, iter)| {
+                let mut taken = 0;
+                while taken < lane.weight() && ids.len() < max {
+                    let Some(id) = iter.next() else {
+                        return false;
+                    };
+
+                    if self.lane_of.get(id) == Some(lane) && ready(*id) && seen.insert(*id) {
+                        ids.push(*id);
+                        taken += 1;
+                    }
+                }
+                true
+            });
+        }
+
+        ids
+    }
+
+    fn drop_stale(&mut self) {
+        for (lane, order) in self.order.iter_mut() {
+            while let Some(id) = order.front() {
+                if self.lane_of.get(id) == Some(lane) {
+                    break;
+                }
+                order.pop_front();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_interleaves_lanes_by_weight() {
+        let lane = |lane: Lane, ids: std::ops::RangeInclusive<u32>| {
+            ids.map(move |id| (id, lane)).collect::<Vec<_>>()
+        };
+
+        // name, ids pushed in order, max, expected ids
+        let cases = [
+            (
+                "every lane has ids",
+                [
+                    lane(Lane::User, 1..=10),
+                    lane(Lane::Refresh, 201..=210),
+                    lane(Lane::Relation, 101..=110),
+                ]
+                .concat(),
+                14,
+                vec![1, 2, 3, 4, 201, 101, 102, 5, 6, 7, 8, 202, 103, 104],
+            ),
+            (
+                "empty lanes give their share to the others",
+                [lane(Lane::User, 1..=2), lane(Lane::Relation, 101..=110)].concat(),
+                7,
+                vec![1, 2, 101, 102, 103, 104, 105],
+            ),
+            (
+                "moved ids are only taken from their new lane",
+                [lane(Lane::Relation, 101..=103), lane(Lane::User, 102..=102)].concat(),
+                3,
+                vec![102, 101, 103],
+            ),
+            (
+                "fewer ids than max",
+                lane(Lane::Refresh, 201..=202),
+                10,
+                vec![201, 202],
+            ),
+        ];
+
+        for (name, pushes, max, expected) in cases {
+            let mut lanes = Lanes::default();
+            for (id, lane) in pushes {
+                lanes.push(id, lane);
+            }
+
+            assert_eq!(lanes.take(max, |_| true), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn take_skips_ids_that_are_not_ready() {
+        let mut lanes = Lanes::default();
+        for id in 1..=6 {
+            lanes.push(id, Lane::User);
+        }
+
+        assert_eq!(lanes.take(3, |id| id % 2 == 0), vec![2, 4, 6]);
+        // Nothing was removed
+        assert_eq!(lanes.len(Lane::User), 6);
+    }
+}
@@ -2,23 +2,55 @@
 // Only allow ids to be in queue once
 // do not readd ids we have seen since processing started
 
+mod actor;
+mod crawl;
+mod lanes;
+mod progress;
+mod refresh;
+
 use std::collections::hash_map::Entry::Vacant;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::str::FromStr;
+use std::sync::Arc;
 
-use chrono::{TimeZone, Utc};
-use reqwest::Client;
-use serde::Serialize;
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
 use sqlx::{MySql, Pool};
-use tokio::time::sleep;
+use tokio::sync::broadcast;
+use tokio::sync::mpsc::UnboundedSender;
 
-use crate::anilist::{
-    get_anime_from_anilist_result, get_animes_from_anilist, MAX_ANILIST_PER_QUERY,
-};
+pub use self::actor::ImporterHandle;
+use self::crawl::CrawlOrigin;
+pub use self::crawl::CrawlSettings;
+pub use self::lanes::Lane;
+use self::lanes::Lanes;
+use self::progress::EntryOutcome;
+pub use self::progress::{ImportProgress, ProgressUpdate};
+pub use self::refresh::RefreshSettings;
 use crate::consts::MYSQL_PARAM_BIND_LIMIT;
-use crate::models::anime::{insert_animes, InsertAnime};
+use crate::metadata::{
+    weekly_airing, AnimeRelation, MetadataBatch, MetadataError, MetadataProvider,
+};
+use crate::metrics;
+use crate::models::airing_schedules::{
+    get_anime_broadcasts, replace_airing_schedules, InsertAiringEpisode,
+};
+use crate::models::anime::{get_existing_anime_ids, insert_animes, InsertAnime};
+use crate::models::anime_details::{replace_anime_details, InsertAnimeDetails};
 use crate::models::anime_relations::create_anime_relation;
 use crate::models::anime_users::link_user_to_anime;
+use crate::models::discovered_animes::{
+    insert_discovered_animes, remove_discovered_animes, DiscoverReason, InsertDiscoveredAnime,
+};
+use crate::models::ignored_animes::{
+    get_ignored_animes, ignore_anime, remove_ignored_animes, IgnoreReason,
+};
+use crate::models::import_jobs::{
+    claim_import_jobs, complete_import_jobs, dead_letter_import_jobs, enqueue_import_jobs,
+    get_dead_import_jobs, get_ignored_import_jobs, get_unfinished_import_jobs, ignore_import_jobs,
+    requeue_import_jobs, retry_import_jobs, DBImportJob,
+};
+use crate::series::update_series;
 
 #[derive(Clone, Debug, Serialize)]
 pub enum AnimeWatchStatus {
@@ -74,21 +106,46 @@ impl From<AnimeWatchStatus> for String {
     }
 }
 
+// The rest of a users list entry, stored alongside the status once the anime is imported
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct UserListStatus {
+    // 0 to 10, 0 = not scored
+    pub score: u32,
+    pub num_episodes_watched: u32,
+    pub is_rewatching: bool,
+    pub start_date: Option<NaiveDate>,
+    pub finish_date: Option<NaiveDate>,
+    // MALs priority, 0 = low to 2 = high
+    pub priority: u32,
+    pub comments: String,
+    // When the entry last changed on MAL
+    pub updated_at: Option<NaiveDateTime>,
+}
+
 #[derive(Clone)]
 pub struct AnimeUserEntry {
     pub anime_id: u32,
     pub user_id: String,
     pub status: AnimeWatchStatus,
+    pub list_status: UserListStatus,
 }
 
 pub struct Importer {
-    reqwest: Client,
+    provider: Arc<dyn MetadataProvider>,
     db: Pool<MySql>,
 
     // The current queue we are processing
     queue: HashMap<u32, Vec<AnimeUserEntry>>,
+    // Which lane each queued id is in and the order they are processed in
+    lanes: Lanes,
     // anime id: Vec<(related anime id, relation type)>
     relation_cache: HashMap<i32, Vec<(u32, String)>>,
+    crawl: CrawlSettings,
+    // Where queued ids were crawled from, ids without one
+    // were not queued from a users list and are not crawled
+    crawl_origins: HashMap<u32, CrawlOrigin>,
+    // Root id: number of animes crawled from it
+    franchise_sizes: HashMap<u32, usize>,
 
     // IDs we have seen recently.
     // IDs here have been processed in the
@@ -96,75 +153,126 @@ pub struct Importer {
     // after all items in queue are processed
     seen_recently: HashSet<u32>,
 
-    // List of IDs to not attempt to import
+    // IDs to not attempt to import, with when they expire
     // Mainly used for ids that do not exist on anilist
-    // TODO: Store this in db so it persists between restarts
-    // Add expiry also
-    ignore_ids: HashSet<u32>,
+    // Mirrors the ignored_animes table
+    ignore_ids: HashMap<u32, NaiveDateTime>,
+
+    // Queued ids that have failed before, they are skipped until
+    // their backoff has passed. Mirrors attempts in the import_jobs table
+    attempts: HashMap<u32, ImportAttempt>,
+
+    // user id: progress of their list import
+    progress: HashMap<String, ImportProgress>,
+    progress_sender: broadcast::Sender<ProgressUpdate>,
+
+    // Halves of batches the provider sent back unparsable data for. They are sent
+    // before anything else, so the anime breaking a batch ends up on its own
+    split_batches: VecDeque<Vec<u32>>,
+
+    // Only one request to the metadata provider is made at a time
+    batch_in_flight: bool,
+    // Set when the provider rate limits us, no batches are started until then
+    paused_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct ImportAttempt {
+    count: u32,
+    retry_at: NaiveDateTime,
+}
+
+// The result of a metadata request made in the background
+pub struct Batch {
+    ids: Vec<u32>,
+    result: Result<MetadataBatch, MetadataError>,
 }
 
 impl Importer {
-    pub fn new(reqwest: Client, db: Pool<MySql>) -> Self {
+    pub fn new(provider: Arc<dyn MetadataProvider>, db: Pool<MySql>, crawl: CrawlSettings) -> Self {
         Importer {
-            reqwest,
+            provider,
             db,
             queue: HashMap::new(),
+            lanes: Lanes::default(),
             relation_cache: HashMap::new(),
+            crawl,
+            crawl_origins: HashMap::new(),
+            franchise_sizes: HashMap::new(),
             seen_recently: HashSet::new(),
-            ignore_ids: HashSet::new(),
+            ignore_ids: HashMap::new(),
+            attempts: HashMap::new(),
+            progress: HashMap::new(),
+            progress_sender: broadcast::channel(64).0,
+            split_batches: VecDeque::new(),
+            batch_in_flight: false,
+            paused_until: None,
         }
     }
 
     pub fn stats(&self) -> ImporterStatus {
         ImporterStatus {
             queue_total: self.queue.len(),
-            ignored_ids: self.ignore_ids.iter().cloned().collect(),
+            lanes: Lane::ALL
+                .iter()
+                .map(|lane| (*lane, self.lanes.len(*lane)))
+                .collect(),
+            retrying: self.attempts.len(),
+            ignored_ids: self.ignore_ids.keys().cloned().collect(),
         }
     }
 
-    // TODO: Clean this up so we can import an anime without a user entry being required
-    // probably need separate hashmaps for this
+    // Queues `id` with a users entry. Every user with the anime on their list gets
+    // an entry in the same slot, adding it again for the same user replaces their status.
+    // Returns false if nothing was queued, like `add_anime_only`
     pub fn add(&mut self, id: u32, user_entry: AnimeUserEntry) -> bool {
-        if self.ignore_ids.contains(&id) {
+        if self.is_ignored(id) {
             tracing::warn!("Tried to insert id {:?}, but it is ignored", id);
             return false;
         }
 
-        let inserted;
-        if let Vacant(e) = self.queue.entry(id) {
-            inserted = true;
-            e.insert(vec![user_entry]);
-        } else {
-            let current = self.queue.get_mut(&id).unwrap();
-            let current_entry = current
-                .iter()
-                .find(|entry| entry.user_id == user_entry.user_id);
-            // TODO: isnt this wrong?
-            if current_entry.is_some() {
-                current.push(user_entry);
-                inserted = true;
-            } else {
-                tracing::warn!(
-                    user_id = user_entry.user_id,
-                    anime_id = id,
-                    "Failed to add user entry to queue."
-                );
-                panic!("Failed to add user entry to queue.");
+        if self.seen_recently.contains(&id) {
+            tracing::warn!(
+                "Tried to insert id {:?}, but it has been imported recently",
+                id
+            );
+            return false;
+        }
+
+        let user_id = user_entry.user_id.clone();
+        let tracked = user_entry.clone();
+        let entries = self.queue.entry(id).or_default();
+        let replaced = match entries
+            .iter_mut()
+            .find(|entry| entry.user_id == user_entry.user_id)
+        {
+            Some(current) => {
+                *current = user_entry;
+                true
+            }
+            None => {
+                entries.push(user_entry);
+                false
             }
+        };
+        self.lanes.push(id, Lane::User);
+        self.crawl_origins.insert(id, CrawlOrigin::root(id));
+        if !replaced {
+            self.track(&[tracked], EntryOutcome::Queued);
         }
 
         tracing::debug!(
             anime = id,
-            user_id = id,
+            user_id = user_id,
             "Anime {} queue with user",
-            if inserted { "added to" } else { "already in" }
+            if replaced { "updated in" } else { "added to" }
         );
 
-        inserted
+        true
     }
 
-    pub fn add_anime_only(&mut self, id: u32) -> bool {
-        if self.ignore_ids.contains(&id) {
+    pub fn add_anime_only(&mut self, id: u32, lane: Lane) -> bool {
+        if self.is_ignored(id) {
             tracing::warn!("Tried to insert id {:?}, but it is ignored", id);
             return false;
         }
@@ -182,6 +290,7 @@ impl Importer {
             inserted = true;
             e.insert(vec![]);
         }
+        self.lanes.push(id, lane);
 
         tracing::debug!(
             anime = id,
@@ -193,185 +302,1017 @@ impl Importer {
         inserted
     }
 
-    pub fn add_all(&mut self, entries: Vec<AnimeUserEntry>) {
-        entries.iter().for_each(|entry| {
-            let anime_id = entry.anime_id;
-            self.add(anime_id, entry.clone());
-        });
+    pub async fn add_all(&mut self, entries: Vec<AnimeUserEntry>) {
+        // Animes imported recently are not fetched again,
+        // the users are linked to them straight away
+        let (recent, entries): (Vec<_>, Vec<_>) = entries
+            .into_iter()
+            .partition(|entry| self.seen_recently.contains(&entry.anime_id));
+        let (ignored, entries): (Vec<_>, Vec<_>) = entries
+            .into_iter()
+            .partition(|entry| self.is_ignored(entry.anime_id));
+
+        let jobs: Vec<(u32, Vec<AnimeUserEntry>)> = entries
+            .into_iter()
+            .filter(|entry| self.add(entry.anime_id, entry.clone()))
+            .map(|entry| (entry.anime_id, vec![entry]))
+            .collect();
+
+        self.persist(jobs).await;
+
+        // Counted as queued and finished straight away, after the rest
+        // are queued so a new import does not reset them
+        let finished: Vec<AnimeUserEntry> = ignored.iter().chain(&recent).cloned().collect();
+        self.track(&finished, EntryOutcome::Queued);
+        self.track(&ignored, EntryOutcome::Ignored);
+        if !recent.is_empty() {
+            self.track(&recent, EntryOutcome::Done);
+            let recent = recent
+                .into_iter()
+                .map(|entry| (entry.anime_id, vec![entry]))
+                .collect();
+            if let Err(err) = link_user_to_anime(&self.db, recent).await {
+                tracing::error!("Failed to link recently imported animes: {:?}", err);
+            }
+        }
     }
 
-    pub async fn process(&mut self) {
-        let items = self.get_items_to_process(MAX_ANILIST_PER_QUERY);
+    pub async fn add_all_anime_only(&mut self, ids: Vec<u32>, lane: Lane) {
+        let jobs: Vec<(u32, Vec<AnimeUserEntry>)> = ids
+            .into_iter()
+            .filter(|id| self.add_anime_only(*id, lane))
+            .map(|id| (id, vec![]))
+            .collect();
 
-        if items.is_empty() {
-            tracing::trace!("No items in queue to process");
+        self.persist(jobs).await;
+    }
+
+    async fn persist(&self, jobs: Vec<(u32, Vec<AnimeUserEntry>)>) {
+        if let Err(err) = enqueue_import_jobs(&self.db, jobs).await {
+            tracing::error!("Failed to persist import jobs: {:?}", err);
+        }
+    }
+
+    fn is_ignored(&self, id: u32) -> bool {
+        self.ignore_ids
+            .get(&id)
+            .is_some_and(|expires_at| *expires_at > Utc::now().naive_utc())
+    }
+
+    async fn ignore(&mut self, id: u32, reason: IgnoreReason, message: String) {
+        let expires_at = Utc::now().naive_utc() + ignore_duration(reason);
+
+        tracing::warn!(anime = id, ?reason, "Ignoring anime until {}", expires_at);
+
+        self.ignore_ids.insert(id, expires_at);
+        if let Some(entries) = self.dequeue(id) {
+            self.track(&entries, EntryOutcome::Ignored);
+        }
+        self.attempts.remove(&id);
+
+        if let Err(err) = ignore_anime(&self.db, id, reason, message.clone(), expires_at).await {
+            tracing::error!("Failed to store ignored anime {}: {:?}", id, err);
+        }
+        // Parked so they are not loaded again on restart, they are
+        // queued again with their users once the ignore expires
+        if let Err(err) = ignore_import_jobs(&self.db, &[id], message, expires_at).await {
+            tracing::error!(
+                "Failed to park import jobs of ignored anime {}: {:?}",
+                id,
+                err
+            );
+        }
+    }
+
+    // Removes ids from the ignore list and queues them to be imported again
+    pub async fn unignore(&mut self, ids: Vec<u32>) {
+        if ids.is_empty() {
             return;
         }
 
-        let ids: Vec<u32> = items.iter().map(|item| item.0).collect();
+        for id in &ids {
+            self.ignore_ids.remove(id);
+        }
+
+        // Jobs parked while the animes were ignored go back in with their users
+        let parked = match get_ignored_import_jobs(&self.db, &ids).await {
+            Ok(jobs) => jobs,
+            Err(err) => {
+                tracing::error!("Failed to load ignored import jobs: {:?}", err);
+                vec![]
+            }
+        };
+        let parked_ids: Vec<u32> = parked.iter().map(|job| job.anime_id as u32).collect();
+        if let Err(err) = requeue_import_jobs(&self.db, &parked_ids, "IGNORED").await {
+            tracing::error!("Failed to requeue ignored import jobs: {:?}", err);
+        }
+        self.restore_jobs(
+            parked
+                .into_iter()
+                .map(|job| DBImportJob { attempts: 0, ..job })
+                .collect(),
+        );
+
+        if let Err(err) = remove_ignored_animes(&self.db, &ids).await {
+            tracing::error!("Failed to remove ignored animes: {:?}", err);
+        }
+
+        tracing::info!("Retrying {} previously ignored animes", ids.len());
+        let ids = ids
+            .into_iter()
+            .filter(|id| !parked_ids.contains(id))
+            .collect();
+        self.add_all_anime_only(ids, Lane::Refresh).await;
+    }
+
+    pub async fn unignore_all(&mut self) {
+        let ids = self.ignore_ids.keys().cloned().collect();
+        self.unignore(ids).await;
+    }
+
+    async fn retry_expired_ignores(&mut self) {
+        let now = Utc::now().naive_utc();
+        let expired: Vec<u32> = self
+            .ignore_ids
+            .iter()
+            .filter(|(_, expires_at)| **expires_at <= now)
+            .map(|(id, _)| *id)
+            .collect();
+
+        self.unignore(expired).await;
+    }
+
+    // Loads the ignore list and any jobs left over from a
+    // previous run back into the queue
+    pub async fn resume(&mut self) {
+        match get_ignored_animes(&self.db).await {
+            Ok(ignored) => {
+                for anime in ignored {
+                    self.ignore_ids
+                        .insert(anime.anime_id as u32, anime.expires_at);
+                }
+            }
+            Err(err) => tracing::error!("Failed to load ignored animes: {:?}", err),
+        }
+
+        let jobs = match get_unfinished_import_jobs(&self.db).await {
+            Ok(jobs) => jobs,
+            Err(err) => {
+                tracing::error!("Failed to load unfinished import jobs: {:?}", err);
+                return;
+            }
+        };
+
+        // Left behind by runs that did not park the jobs of ignored animes
+        let (ignored, jobs): (Vec<_>, Vec<_>) = jobs
+            .into_iter()
+            .partition(|job| self.is_ignored(job.anime_id as u32));
+        let ignored: HashSet<u32> = ignored.iter().map(|job| job.anime_id as u32).collect();
+        for id in ignored {
+            let expires_at = self.ignore_ids[&id];
+            let message = "Anime is ignored".to_string();
+            if let Err(err) = ignore_import_jobs(&self.db, &[id], message, expires_at).await {
+                tracing::error!(
+                    "Failed to park import jobs of ignored anime {}: {:?}",
+                    id,
+                    err
+                );
+            }
+        }
+
+        let total = jobs.len();
+        self.restore_jobs(jobs);
+
+        tracing::info!("Resumed {} unfinished import jobs", total);
+    }
+
+    fn restore_jobs(&mut self, jobs: Vec<DBImportJob>) {
+        for job in jobs {
+            let anime_id = job.anime_id as u32;
+            // Attempts are stored on every row of the anime, the
+            // rows can differ so the most attempts is used
+            let count = job.attempts.max(0) as u32;
+            let known = self
+                .attempts
+                .get(&anime_id)
+                .map_or(0, |attempt| attempt.count);
+            if count > known {
+                self.attempts.insert(
+                    anime_id,
+                    ImportAttempt {
+                        count,
+                        retry_at: job.next_attempt_at.unwrap_or(job.updated_at),
+                    },
+                );
+            }
+
+            match job.watch_status {
+                Some(status) if !job.user_id.is_empty() => {
+                    self.add(
+                        anime_id,
+                        AnimeUserEntry {
+                            anime_id,
+                            user_id: job.user_id,
+                            status: status.into(),
+                            // Jobs from before the rest of the entry was stored have none
+                            list_status: job
+                                .list_status
+                                .and_then(|list_status| serde_json::from_str(&list_status).ok())
+                                .unwrap_or_default(),
+                        },
+                    );
+                }
+                _ => {
+                    // The lane is not stored, so resumed jobs without
+                    // a user wait behind user and refresh imports
+                    self.add_anime_only(anime_id, Lane::Relation);
+                }
+            }
+        }
+    }
+
+    // Moves dead lettered jobs back into the queue with their attempts reset
+    pub async fn requeue_dead(&mut self, ids: Vec<u32>) {
+        let jobs = match get_dead_import_jobs(&self.db).await {
+            Ok(jobs) => jobs,
+            Err(err) => {
+                tracing::error!("Failed to load dead import jobs: {:?}", err);
+                return;
+            }
+        };
+
+        let jobs: Vec<DBImportJob> = jobs
+            .into_iter()
+            .filter(|job| ids.contains(&(job.anime_id as u32)))
+            .map(|job| DBImportJob { attempts: 0, ..job })
+            .collect();
+
+        let ids: Vec<u32> = jobs.iter().map(|job| job.anime_id as u32).collect();
+        if let Err(err) = requeue_import_jobs(&self.db, &ids, "DEAD").await {
+            tracing::error!("Failed to requeue dead import jobs: {:?}", err);
+            return;
+        }
+
+        // An admin asked for these, so they are imported again even if
+        // another job for the same anime finished recently
+        for id in &ids {
+            self.seen_recently.remove(id);
+            self.attempts.remove(id);
+        }
+
+        tracing::info!("Requeued {} dead import jobs", jobs.len());
+        self.restore_jobs(jobs);
+    }
+
+    pub async fn requeue_all_dead(&mut self) {
+        let ids = match get_dead_import_jobs(&self.db).await {
+            Ok(jobs) => jobs.iter().map(|job| job.anime_id as u32).collect(),
+            Err(err) => {
+                tracing::error!("Failed to load dead import jobs: {:?}", err);
+                return;
+            }
+        };
+
+        self.requeue_dead(ids).await;
+    }
+
+    // Backs off every id in a failed batch, ids that have
+    // failed too many times are moved to the dead letter list
+    async fn retry_later(&mut self, ids: &[u32], error: String) {
+        let now = Utc::now().naive_utc();
+        // attempt count: ids that failed that many times
+        let mut retries: HashMap<u32, Vec<u32>> = HashMap::new();
+        let mut dead = vec![];
+
+        for id in ids {
+            let count = self.attempts.get(id).map_or(0, |attempt| attempt.count) + 1;
+            if count >= MAX_IMPORT_ATTEMPTS {
+                if let Some(entries) = self.dequeue(*id) {
+                    self.track(&entries, EntryOutcome::Failed);
+                }
+                self.attempts.remove(id);
+                dead.push(*id);
+                continue;
+            }
+
+            self.attempts.insert(
+                *id,
+                ImportAttempt {
+                    count,
+                    retry_at: now + retry_backoff(count),
+                },
+            );
+            retries.entry(count).or_default().push(*id);
+        }
+
+        for (count, ids) in retries {
+            let retry_at = now + retry_backoff(count);
+            tracing::warn!("Retrying {} animes after {}", ids.len(), retry_at);
+            if let Err(err) =
+                retry_import_jobs(&self.db, &ids, count, error.clone(), retry_at).await
+            {
+                tracing::error!("Failed to store import job attempts: {:?}", err);
+            }
+        }
+
+        if !dead.is_empty() {
+            tracing::error!(
+                "Giving up on {} animes after {} attempts",
+                dead.len(),
+                MAX_IMPORT_ATTEMPTS
+            );
+            if let Err(err) = dead_letter_import_jobs(&self.db, &dead, error).await {
+                tracing::error!("Failed to dead letter import jobs: {:?}", err);
+            }
+        }
+    }
+
+    // Removes ids from the queue without importing them
+    pub async fn cancel(&mut self, ids: Vec<u32>) {
+        for id in &ids {
+            if let Some(entries) = self.dequeue(*id) {
+                self.track(&entries, EntryOutcome::Cancelled);
+            }
+            self.attempts.remove(id);
+        }
+
+        let _ = complete_import_jobs(&self.db, &ids).await;
+        tracing::info!("Cancelled {} queued animes", ids.len());
+    }
+
+    // Takes the next items from the queue and requests them from the
+    // metadata provider in the background. The result is sent back through `sender`
+    // so the importer can keep handling commands while it waits
+    async fn start_batch(&mut self, sender: &UnboundedSender<Batch>) {
+        if self.batch_in_flight {
+            return;
+        }
+
+        if let Some(paused_until) = self.paused_until {
+            if paused_until > Utc::now() {
+                tracing::trace!("Waiting for rate limit to reset");
+                return;
+            }
+            self.paused_until = None;
+        }
+
+        self.retry_expired_ignores().await;
+
+        let ids = self.next_batch_ids();
+
+        if ids.is_empty() {
+            tracing::trace!("No items in queue to process");
+            // Queue has drained, allow everything to be imported again
+            // so scheduled refreshes are not skipped
+            if self.queue.is_empty() {
+                self.seen_recently.clear();
+                self.crawl_origins.clear();
+                self.franchise_sizes.clear();
+            }
+            return;
+        }
 
         tracing::debug!("Processing {:?} items", ids.len());
-        let animes = get_animes_from_anilist(&self.reqwest, ids).await;
+        if let Err(err) = claim_import_jobs(&self.db, &ids).await {
+            tracing::error!("Failed to claim import jobs: {:?}", err);
+        }
+
+        self.batch_in_flight = true;
+        let provider = self.provider.clone();
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            let result = provider.fetch(ids.clone()).await;
+            let _ = sender.send(Batch { ids, result });
+        });
+    }
 
-        // Handle anilist rate limits
-        if animes.rate_limit_reset != -1 {
+    async fn finish_batch(&mut self, batch: Batch) {
+        self.batch_in_flight = false;
+        let Batch { ids, result } = batch;
+
+        let metadata = match result {
+            Ok(metadata) => metadata,
+            Err(MetadataError::RateLimited(until)) => {
+                tracing::warn!("Rate limited by {} until {:?}", self.provider.name(), until);
+                let sleep = (until - Utc::now()).num_milliseconds().max(0) as f64 / 1000.0;
+                metrics::inc_counter("importer_batches_total", &[("result", "rate_limited")]);
+                metrics::inc_counter("rate_limit_sleeps_total", &[("reason", "rate_limited")]);
+                metrics::inc_counter_by(
+                    "rate_limit_sleep_seconds_total",
+                    &[("reason", "rate_limited")],
+                    sleep,
+                );
+                self.paused_until = Some(until);
+                return;
+            }
+            // A single anime that we get unparsable data for
+            // would otherwise be retried forever
+            Err(err @ MetadataError::Parse(_)) if ids.len() == 1 => {
+                metrics::inc_counter("importer_batches_total", &[("result", "failed")]);
+                self.ignore(ids[0], IgnoreReason::ParseFailure, err.to_string())
+                    .await;
+                return;
+            }
+            // One bad anime fails the whole response
+            Err(MetadataError::Parse(err)) => {
+                tracing::warn!(
+                    "Failed to parse batch of {} animes, splitting it: {}",
+                    ids.len(),
+                    err
+                );
+                metrics::inc_counter("importer_batches_total", &[("result", "split")]);
+                let (first, second) = ids.split_at(ids.len() / 2);
+                self.split_batches.push_front(second.to_vec());
+                self.split_batches.push_front(first.to_vec());
+                return;
+            }
+            // The provider lowered its batch size, the ids are taken
+            // again straight away in smaller batches
+            Err(err @ MetadataError::TooLarge(_)) if ids.len() > 1 => {
+                tracing::warn!("Batch of {} animes was too large: {}", ids.len(), err);
+                metrics::inc_counter("importer_batches_total", &[("result", "too_large")]);
+                return;
+            }
+            Err(err) => {
+                // Items are left in the queue and picked up again once their backoff passes
+                tracing::error!("Failed to get animes: {}", err);
+                metrics::inc_counter("importer_batches_total", &[("result", "failed")]);
+                self.retry_later(&ids, err.to_string()).await;
+                return;
+            }
+        };
+
+        // Ids that were neither returned nor reported missing, eg. after an error for
+        // just that anime. They are backed off like a failed batch, otherwise they
+        // would be requested again on every tick
+        let answered: HashSet<u32> = metadata
+            .animes
+            .iter()
+            .map(|anime| anime.id_mal)
+            .chain(metadata.not_found.iter().copied())
+            .collect();
+        let unanswered: Vec<u32> = ids
+            .iter()
+            .filter(|id| !answered.contains(id))
+            .copied()
+            .collect();
+        if !unanswered.is_empty() {
             tracing::warn!(
-                "Rate limited by anilist for {:?}, waiting now...",
-                animes.rate_limit_reset
+                "{} did not return {} of {} animes",
+                self.provider.name(),
+                unanswered.len(),
+                ids.len()
             );
-            let now = Utc::now();
-            let target_time = Utc
-                .timestamp_opt(animes.rate_limit_reset.into(), 0)
-                .single()
-                .expect("Failed to parse anilist rate limit reset into duration");
-            sleep(
-                (target_time - now)
-                    .to_std()
-                    .expect("Failed to convert chrone duration to std duration"),
+            self.retry_later(
+                &unanswered,
+                format!("Not returned by {}", self.provider.name()),
             )
             .await;
         }
 
-        if let Ok(anilist_response) = animes.response {
-            if let Some(errors) = anilist_response.errors.clone() {
-                // Only handle first for now, never seen it return multiple errors
-                // for the requests we do
-                let error = errors.first();
-                if let Some(error) = error {
-                    if error.status == 404 {
-                        // Same here, never seen it return multiple
-                        let loc = error.locations.first();
-                        if let Some(loc) = loc {
-                            let total = animes.query.query.lines().count();
-                            let lpq = total / items.len();
-                            let error_index = (loc.line - 1) as usize / lpq;
-                            let errored_item =
-                                items.get(error_index).expect("No item for error given");
-                            tracing::error!(
-                                "Anime {:?} has not found on anilist, adding to ignore list",
-                                errored_item.0,
-                            );
-
-                            self.ignore_ids.insert(errored_item.0);
-                            self.queue.remove(&errored_item.0);
-                            // Just end this loop and pickup again on the next
-                            // TODO: Retry here instead of exiting?
-                            return;
-                        }
-                    }
-                }
-            }
+        for id in metadata.not_found {
+            tracing::error!("Anime {:?} was not found, adding to ignore list", id);
+            self.ignore(
+                id,
+                IgnoreReason::AnilistNotFound,
+                format!("Not found by {}", self.provider.name()),
+            )
+            .await;
+        }
 
-            tracing::info!("Got animes from anilist");
+        metrics::inc_counter("importer_batches_total", &[("result", "ok")]);
+        let anime_data = metadata.animes;
+        let imported_ids: Vec<u32> = anime_data.iter().map(|anime| anime.id_mal).collect();
+        // Entries may have been added while the request was in flight. They stay
+        // queued until they are stored, so a failed write is retried with them
+        let user_entries: Vec<(u32, Vec<AnimeUserEntry>)> = imported_ids
+            .iter()
+            .filter_map(|id| {
+                let entries = self.queue.get(id)?;
+                Some((*id, entries.clone()))
+            })
+            .collect();
 
-            let mut anime_data = vec![];
+        tracing::info!("Got {:?} animes", anime_data.len());
 
-            for anime_index in 0..MAX_ANILIST_PER_QUERY {
-                let anime = get_anime_from_anilist_result(anilist_response.clone(), anime_index);
+        // Animes anilist has no airing schedule for, and every anime from jikan,
+        // get one worked out from the weekly broadcast on their MAL list entries
+        let unscheduled: Vec<u32> = anime_data
+            .iter()
+            .filter(|anime| anime.airing.is_empty())
+            .filter(|anime| matches!(anime.status.as_str(), "RELEASING" | "NOT_YET_RELEASED"))
+            .map(|anime| anime.id_mal)
+            .collect();
+        let broadcasts = if unscheduled.is_empty() {
+            vec![]
+        } else {
+            get_anime_broadcasts(&self.db, &unscheduled)
+                .await
+                .unwrap_or_else(|err| {
+                    tracing::error!("Failed to get anime broadcasts: {:?}", err);
+                    vec![]
+                })
+        };
 
-                if anime.is_none() {
-                    tracing::debug!("Last found animeIndex was {:?}", anime_index);
-                    break;
-                }
-                let anime = anime.unwrap();
-                // TODO: Can I make the animes not have optional everything here?
-                let anime_id = anime.id_mal.unwrap();
-                anime_data.push(anime);
-                self.queue.remove(&anime_id);
-                self.seen_recently.insert(anime_id);
+        let now = Utc::now();
+        let airing: Vec<InsertAiringEpisode> = anime_data
+            .iter()
+            .flat_map(|anime| {
+                let airing = match broadcasts
+                    .iter()
+                    .find(|broadcast| broadcast.anime_id as u32 == anime.id_mal)
+                {
+                    Some(broadcast) if anime.airing.is_empty() => weekly_airing(
+                        &broadcast.day_of_the_week,
+                        &broadcast.start_time,
+                        anime.start_date,
+                        anime.episodes,
+                        now,
+                    ),
+                    _ => anime.airing.clone(),
+                };
+                airing.into_iter().map(|episode| InsertAiringEpisode {
+                    anime_id: anime.id_mal,
+                    episode: episode.episode,
+                    airing_at: episode.airing_at,
+                })
+            })
+            .collect();
+        let relations: Vec<(u32, Vec<AnimeRelation>)> = anime_data
+            .iter()
+            .map(|anime| (anime.id_mal, anime.relations.clone()))
+            .collect();
+
+        let (formatted, details): (Vec<_>, Vec<_>) = anime_data
+            .into_iter()
+            .map(|anime| {
+                let details = InsertAnimeDetails {
+                    anime_id: anime.id_mal,
+                    synonyms: anime.synonyms,
+                    genres: anime.genres,
+                    tags: anime.tags,
+                    studios: anime.studios,
+                };
+                let anime = InsertAnime {
+                    id_mal: anime.id_mal,
+                    romaji_title: anime.romaji_title,
+                    english_title: anime.english_title,
+                    native_title: anime.native_title,
+                    status: anime.status,
+                    format: anime.format,
+                    season: anime.season,
+                    picture: anime.picture,
+                    banner_image: anime.banner_image,
+                    description: anime.description,
+                    season_year: anime.season_year,
+                    start_date: anime.start_date,
+                    end_date: anime.end_date,
+                    episodes: anime.episodes,
+                    duration: anime.duration,
+                    average_score: anime.average_score,
+                    popularity: anime.popularity,
+                    is_adult: anime.is_adult,
+                };
+                (anime, details)
+            })
+            .unzip();
+
+        // The job rows are only completed once the animes and the users
+        // entries are stored, otherwise the entries would be lost
+        if let Err(err) = insert_animes(&self.db, formatted).await {
+            tracing::error!("Failed to store animes: {:?}", err);
+            self.retry_later(&imported_ids, err.to_string()).await;
+            return;
+        }
+        if let Err(err) = replace_anime_details(&self.db, details).await {
+            tracing::error!("Failed to store anime details: {:?}", err);
+        }
+        if let Err(err) = replace_airing_schedules(&self.db, &imported_ids, airing).await {
+            tracing::error!("Failed to store airing schedules: {:?}", err);
+        }
+        if let Err(err) = link_user_to_anime(&self.db, user_entries).await {
+            tracing::error!("Failed to link users to animes: {:?}", err);
+            self.retry_later(&imported_ids, err.to_string()).await;
+            return;
+        }
+
+        for anime_id in imported_ids.iter() {
+            self.attempts.remove(anime_id);
+            if let Some(entries) = self.dequeue(*anime_id) {
+                self.track(&entries, EntryOutcome::Done);
+            }
+            self.seen_recently.insert(*anime_id);
+        }
+
+        if let Err(err) = complete_import_jobs(&self.db, &imported_ids).await {
+            tracing::error!("Failed to complete import jobs: {:?}", err);
+        }
+        let _ = remove_discovered_animes(&self.db, &imported_ids).await;
+
+        let mut related_ids = vec![];
+        let mut not_crawled = vec![];
+        for (anime_id, relations) in relations {
+            let origin = self.crawl_origins.remove(&anime_id);
+
+            if relations.is_empty() {
+                tracing::debug!(anime = anime_id, "Anime had no relations");
+                continue;
             }
 
-            tracing::info!("Got {:?} animes from anilist", anime_data.len());
+            for relation in relations {
+                let mal_id = anime_id as i32;
+                let new_relation = (relation.id_mal, relation.relation_type.clone());
+                if let Vacant(e) = self.relation_cache.entry(mal_id) {
+                    e.insert(vec![new_relation]);
+                } else {
+                    let value = self.relation_cache.get_mut(&mal_id).unwrap();
+                    value.push(new_relation);
+                }
 
-            for anime in anime_data.clone() {
-                if anime.relations.is_none() {
-                    tracing::debug!(anime = anime.id_mal, "Anime had no relations");
+                if !self.crawl.relation_types.contains(&relation.relation_type) {
+                    tracing::debug!(
+                        relation = relation.id_mal,
+                        anime = anime_id,
+                        "Not crawling {} relation",
+                        relation.relation_type
+                    );
                     continue;
                 }
 
-                let relations = anime.relations.unwrap().edges;
-
-                for relation in relations {
-                    if relation.node.id_mal.is_none() {
-                        tracing::debug!(
-                            relation = relation.node.id_mal,
-                            anime = anime.id_mal,
-                            "Relation had no mal id"
-                        );
-                        continue;
-                    }
-
-                    if relation.relation_type != "PREQUEL" && relation.relation_type != "SEQUEL" {
-                        tracing::debug!(
-                            relation = relation.node.id_mal,
-                            anime = anime.id_mal,
-                            "Relation is not prequel or sequal"
-                        );
-                        continue;
-                    }
-
-                    let mal_id = relation.node.id_mal.unwrap();
-                    let new_relation = (mal_id as u32, relation.relation_type);
-                    if let Vacant(e) = self.relation_cache.entry(mal_id) {
-                        e.insert(vec![new_relation]);
-                    } else {
-                        let value = self.relation_cache.get_mut(&mal_id).unwrap();
-                        value.push(new_relation);
-                    }
-                    self.add_anime_only(mal_id as u32);
+                match self.crawl_relation(origin, relation.id_mal) {
+                    Ok(()) => related_ids.push(relation.id_mal),
+                    Err(reason) => not_crawled.push(InsertDiscoveredAnime {
+                        anime_id: relation.id_mal,
+                        from_anime_id: anime_id,
+                        reason,
+                        depth: origin.map_or(0, |origin| origin.depth + 1),
+                    }),
                 }
             }
+        }
+        self.add_all_anime_only(related_ids, Lane::Relation).await;
+        self.record_not_crawled(not_crawled).await;
 
-            let formatted = anime_data
-                .iter()
-                // Add related animes to queue and
-                // TODO: CLONE AAAAAAAa
-                .map(|anime| InsertAnime {
-                    id_mal: anime.id_mal.unwrap(),
-                    title: anime.title.clone(),
-                    status: anime.status.clone(),
-                    season: anime.season.clone(),
-                    cover_image: anime.cover_image.clone(),
-                    season_year: anime.season_year,
-                })
-                .collect();
+        let _ = self.proces_relations().await;
+
+        if let Err(err) = update_series(&self.db, &imported_ids).await {
+            tracing::error!("Failed to update anime series: {:?}", err);
+        }
+    }
+
+    // Records the crawl origin of `related_id` if it is within the crawl limits
+    fn crawl_relation(
+        &mut self,
+        origin: Option<CrawlOrigin>,
+        related_id: u32,
+    ) -> Result<(), DiscoverReason> {
+        let Some(origin) = origin else {
+            return Err(DiscoverReason::Untracked);
+        };
+
+        let franchise_size = self.franchise_sizes.entry(origin.root).or_default();
+        if let Some(reason) = self.crawl.check(origin, *franchise_size) {
+            tracing::debug!(
+                anime = related_id,
+                root = origin.root,
+                ?reason,
+                "Not crawling relation"
+            );
+            return Err(reason);
+        }
+
+        *franchise_size += 1;
+        let next = origin.next();
+        self.crawl_origins
+            .entry(related_id)
+            .and_modify(|current| {
+                if next.depth < current.depth {
+                    *current = next;
+                }
+            })
+            .or_insert(next);
+
+        Ok(())
+    }
+
+    // Only animes we do not have are worth recording
+    async fn record_not_crawled(&self, animes: Vec<InsertDiscoveredAnime>) {
+        if animes.is_empty() {
+            return;
+        }
+
+        let ids: Vec<u32> = animes.iter().map(|anime| anime.anime_id).collect();
+        let existing: HashSet<u32> = match get_existing_anime_ids(&self.db, &ids).await {
+            Ok(existing) => existing.into_iter().map(|id| id as u32).collect(),
+            Err(err) => {
+                tracing::error!("Failed to check discovered animes: {:?}", err);
+                return;
+            }
+        };
 
-            let _ = insert_animes(&self.db, formatted).await;
-            let _ = link_user_to_anime(&self.db, items).await;
+        let animes = animes
+            .into_iter()
+            .filter(|anime| !existing.contains(&anime.anime_id))
+            .collect();
 
-            let _ = self.proces_relations().await;
+        if let Err(err) = insert_discovered_animes(&self.db, animes).await {
+            tracing::error!("Failed to record discovered animes: {:?}", err);
         }
     }
 
-    async fn proces_relations(&self) {
+    async fn proces_relations(&mut self) {
         let insert_items: Vec<(u32, u32, String)> = self
             .relation_cache
-            .iter()
-            .take(MYSQL_PARAM_BIND_LIMIT / 3)
-            .flat_map(|entry| {
-                let anime_id = entry.0;
-                let relations = entry.1;
-
+            .drain()
+            .flat_map(|(anime_id, relations)| {
                 relations
-                    .iter()
-                    .map(|rel| (*anime_id as u32, rel.0, rel.1.clone()))
+                    .into_iter()
+                    .map(move |rel| (anime_id as u32, rel.0, rel.1))
             })
             .collect();
 
-        let _ = create_anime_relation(&self.db, insert_items).await;
+        for group in insert_items.chunks(MYSQL_PARAM_BIND_LIMIT / 3) {
+            if let Err(err) = create_anime_relation(&self.db, group.to_vec()).await {
+                tracing::error!("Failed to insert anime relations: {}", err);
+            }
+        }
     }
 
-    fn get_items_to_process(&self, max: usize) -> Vec<(u32, Vec<AnimeUserEntry>)> {
-        self.queue
-            .iter()
-            .take(max)
-            .map(|(&key, value)| (key, value.clone()))
-            .collect()
+    fn dequeue(&mut self, id: u32) -> Option<Vec<AnimeUserEntry>> {
+        self.lanes.remove(id);
+        self.queue.remove(&id)
+    }
+
+    // Halves of split batches go first, ids removed from the queue since are dropped
+    fn next_batch_ids(&mut self) -> Vec<u32> {
+        match self.split_batches.pop_front() {
+            Some(ids) => ids
+                .into_iter()
+                .filter(|id| self.queue.contains_key(id))
+                .collect(),
+            None => self.get_ids_to_process(self.provider.batch_size()),
+        }
+    }
+
+    // Ids still backing off from a failed attempt are skipped
+    fn get_ids_to_process(&mut self, max: usize) -> Vec<u32> {
+        let now = Utc::now().naive_utc();
+        let attempts = &self.attempts;
+        self.lanes.take(max, |id| match attempts.get(&id) {
+            Some(attempt) => attempt.retry_at <= now,
+            None => true,
+        })
+    }
+}
+
+// Failed batches are retried this many times before
+// their animes are moved to the dead letter list
+const MAX_IMPORT_ATTEMPTS: u32 = 8;
+
+// 30s, 1m, 2m, ... capped at 6 hours
+fn retry_backoff(attempt: u32) -> Duration {
+    let backoff = Duration::seconds(30) * 2i32.pow(attempt.saturating_sub(1).min(16));
+    backoff.min(Duration::hours(6))
+}
+
+// Anilist often adds mal mappings for new animes a while after they are
+// added to mal, so missing animes are retried more often than broken ones
+fn ignore_duration(reason: IgnoreReason) -> Duration {
+    match reason {
+        IgnoreReason::AnilistNotFound => Duration::days(3),
+        IgnoreReason::ParseFailure => Duration::days(7),
     }
 }
 
 #[derive(Serialize)]
 pub struct ImporterStatus {
-    queue_total: usize,
-    ignored_ids: Vec<u32>,
+    pub queue_total: usize,
+    pub lanes: HashMap<Lane, usize>,
+    pub retrying: usize,
+    pub ignored_ids: Vec<u32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use sqlx::mysql::MySqlPoolOptions;
+
+    use super::*;
+
+    struct NoopProvider;
+
+    #[async_trait]
+    impl MetadataProvider for NoopProvider {
+        fn name(&self) -> &'static str {
+            "noop"
+        }
+
+        fn batch_size(&self) -> usize {
+            50
+        }
+
+        async fn fetch(&self, _ids: Vec<u32>) -> Result<MetadataBatch, MetadataError> {
+            Ok(MetadataBatch::default())
+        }
+    }
+
+    // The pool never connects, `add` and `add_anime_only` do not touch the database
+    // and anything that does fails straight away
+    fn importer() -> Importer {
+        let db = MySqlPoolOptions::new()
+            .acquire_timeout(std::time::Duration::from_millis(100))
+            .connect_lazy("mysql://localhost/sei")
+            .unwrap();
+
+        Importer::new(Arc::new(NoopProvider), db, CrawlSettings::default())
+    }
+
+    fn entry(anime_id: u32, user_id: &str, status: AnimeWatchStatus) -> AnimeUserEntry {
+        AnimeUserEntry {
+            anime_id,
+            user_id: user_id.to_string(),
+            status,
+            list_status: UserListStatus::default(),
+        }
+    }
+
+    fn statuses(importer: &Importer, id: u32) -> Vec<(String, String)> {
+        importer.queue[&id]
+            .iter()
+            .map(|entry| (entry.user_id.clone(), entry.status.clone().into()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn second_user_merges_into_queued_anime() {
+        let mut importer = importer();
+
+        assert!(importer.add(1, entry(1, "a", AnimeWatchStatus::Watching)));
+        assert!(importer.add(1, entry(1, "b", AnimeWatchStatus::Completed)));
+
+        assert_eq!(importer.queue.len(), 1);
+        assert_eq!(
+            statuses(&importer, 1),
+            vec![
+                ("a".to_string(), "watching".to_string()),
+                ("b".to_string(), "completed".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn same_user_replaces_status() {
+        let mut importer = importer();
+
+        assert!(importer.add(1, entry(1, "a", AnimeWatchStatus::PlanToWatch)));
+        assert!(importer.add(1, entry(1, "a", AnimeWatchStatus::Watching)));
+
+        assert_eq!(
+            statuses(&importer, 1),
+            vec![("a".to_string(), "watching".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn user_entry_joins_anime_only_slot() {
+        let mut importer = importer();
+
+        assert!(importer.add_anime_only(1, Lane::Relation));
+        assert!(importer.add(1, entry(1, "a", AnimeWatchStatus::Dropped)));
+        assert!(!importer.add_anime_only(1, Lane::Relation));
+
+        assert_eq!(
+            statuses(&importer, 1),
+            vec![("a".to_string(), "dropped".to_string())]
+        );
+        assert_eq!(importer.lanes.len(Lane::User), 1);
+        assert_eq!(importer.lanes.len(Lane::Relation), 0);
+    }
+
+    #[tokio::test]
+    async fn recently_imported_is_not_queued() {
+        let mut importer = importer();
+        importer.seen_recently.insert(1);
+
+        assert!(!importer.add(1, entry(1, "a", AnimeWatchStatus::Watching)));
+        assert!(!importer.add_anime_only(1, Lane::Relation));
+        assert!(importer.queue.is_empty());
+    }
+
+    #[tokio::test]
+    async fn unparsable_batch_is_split() {
+        let mut importer = importer();
+        for id in 1..=5 {
+            importer.add_anime_only(id, Lane::Relation);
+        }
+
+        let ids = importer.next_batch_ids();
+        assert_eq!(ids, vec![1, 2, 3, 4, 5]);
+        importer
+            .finish_batch(Batch {
+                ids,
+                result: Err(MetadataError::Parse(anyhow::anyhow!("bad item"))),
+            })
+            .await;
+
+        assert_eq!(importer.next_batch_ids(), vec![1, 2]);
+        assert_eq!(importer.next_batch_ids(), vec![3, 4, 5]);
+        assert_eq!(importer.next_batch_ids(), vec![1, 2, 3, 4, 5]);
+        // Splitting is not a failed attempt
+        assert!(importer.attempts.is_empty());
+    }
+
+    #[tokio::test]
+    async fn too_large_batch_is_not_a_failed_attempt() {
+        let mut importer = importer();
+        for id in 1..=3 {
+            importer.add_anime_only(id, Lane::Relation);
+        }
+
+        let ids = importer.next_batch_ids();
+        importer
+            .finish_batch(Batch {
+                ids,
+                result: Err(MetadataError::TooLarge(anyhow::anyhow!("too complex"))),
+            })
+            .await;
+
+        assert!(importer.attempts.is_empty());
+        assert_eq!(importer.next_batch_ids(), vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn restored_jobs_keep_the_most_attempts() {
+        let mut importer = importer();
+        let now = Utc::now().naive_utc();
+        let job = |user_id: &str, attempts: i32| DBImportJob {
+            anime_id: 1,
+            user_id: user_id.to_string(),
+            watch_status: Some("WATCHING".to_string()),
+            list_status: None,
+            status: "FAILED".to_string(),
+            error: None,
+            attempts,
+            next_attempt_at: None,
+            created_at: now,
+            updated_at: now,
+        };
+
+        importer.restore_jobs(vec![job("a", 5), job("b", 2), job("c", 0)]);
+
+        assert_eq!(importer.attempts[&1].count, 5);
+        assert_eq!(importer.queue[&1].len(), 3);
+    }
+
+    #[tokio::test]
+    async fn progress_counts_ignored_and_recent_entries_once() {
+        let mut importer = importer();
+        importer
+            .ignore_ids
+            .insert(2, Utc::now().naive_utc() + Duration::days(1));
+        importer.seen_recently.insert(3);
+        let list = || {
+            vec![
+                entry(1, "a", AnimeWatchStatus::Watching),
+                entry(2, "a", AnimeWatchStatus::Watching),
+                entry(3, "a", AnimeWatchStatus::Watching),
+            ]
+        };
+
+        importer.add_all(list()).await;
+        let progress = importer.progress("a");
+        assert_eq!(
+            (progress.queued, progress.done, progress.ignored),
+            (1, 1, 1)
+        );
+
+        let entries = importer.dequeue(1).unwrap();
+        importer.track(&entries, EntryOutcome::Done);
+        let progress = importer.progress("a");
+        assert_eq!(
+            (progress.queued, progress.done, progress.ignored),
+            (0, 2, 1)
+        );
+
+        // Syncing again starts a new import rather than adding to the old totals
+        importer.seen_recently.insert(1);
+        importer.add_all(list()).await;
+        let progress = importer.progress("a");
+        assert_eq!(
+            (progress.queued, progress.done, progress.ignored),
+            (0, 2, 1)
+        );
+    }
+
+    #[tokio::test]
+    async fn ignored_is_not_queued() {
+        let mut importer = importer();
+        importer
+            .ignore_ids
+            .insert(1, Utc::now().naive_utc() + Duration::days(1));
+
+        assert!(!importer.add(1, entry(1, "a", AnimeWatchStatus::Watching)));
+        assert!(!importer.add_anime_only(1, Lane::Relation));
+        assert!(importer.queue.is_empty());
+    }
 }
@@ -0,0 +1,79 @@
+use std::collections::HashSet;
+
+use serde::Serialize;
+
+use super::{AnimeUserEntry, Importer};
+
+// How far the import of a users list has got. Counts are per
+// list entry and start again once everything queued has finished
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ImportProgress {
+    pub queued: usize,
+    pub done: usize,
+    pub failed: usize,
+    pub ignored: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct ProgressUpdate {
+    pub user_id: String,
+    pub progress: ImportProgress,
+}
+
+// What happened to a queued user entry
+#[derive(Clone, Copy, Debug)]
+pub enum EntryOutcome {
+    Queued,
+    Done,
+    Failed,
+    Ignored,
+    Cancelled,
+}
+
+impl ImportProgress {
+    fn apply(&mut self, outcome: EntryOutcome) {
+        if let EntryOutcome::Queued = outcome {
+            // A new import after the last one finished
+            if self.queued == 0 {
+                *self = ImportProgress::default();
+            }
+            self.queued += 1;
+            return;
+        }
+
+        self.queued = self.queued.saturating_sub(1);
+        match outcome {
+            EntryOutcome::Done => self.done += 1,
+            EntryOutcome::Failed => self.failed += 1,
+            EntryOutcome::Ignored => self.ignored += 1,
+            EntryOutcome::Queued | EntryOutcome::Cancelled => {}
+        }
+    }
+}
+
+impl Importer {
+    pub fn progress(&self, user_id: &str) -> ImportProgress {
+        self.progress.get(user_id).cloned().unwrap_or_default()
+    }
+
+    // Updates the progress of every user in `entries`
+    // and sends the new totals to anyone listening
+    pub(super) fn track(&mut self, entries: &[AnimeUserEntry], outcome: EntryOutcome) {
+        let mut changed = HashSet::new();
+        for entry in entries {
+            self.progress
+                .entry(entry.user_id.clone())
+                .or_default()
+                .apply(outcome);
+            changed.insert(entry.user_id.as_str());
+        }
+
+        for user_id in changed {
+            // Only fails when nobody is subscribed
+            let _ = self.progress_sender.send(ProgressUpdate {
+                user_id: user_id.to_string(),
+                progress: self.progress(user_id),
+            });
+        }
+    }
+}
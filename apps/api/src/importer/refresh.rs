@@ -0,0 +1,106 @@
+use chrono::{Duration, Utc};
+use sqlx::{MySql, Pool};
+use tokio::time;
+
+use super::{ImporterHandle, Lane};
+use crate::models::anime::get_stale_anime_ids;
+
+// How often and how aggressively stored animes are re-imported
+// Animes are only refreshed when they are older than their max age
+#[derive(Clone, Debug)]
+pub struct RefreshSettings {
+    pub interval: Duration,
+    // Max age of RELEASING and NOT_YET_RELEASED animes
+    pub airing_max_age: Duration,
+    // Max age of everything else
+    pub finished_max_age: Duration,
+    // Max animes queued per run, so the refresh lane does
+    // not grow faster than it can be processed
+    pub batch_limit: u32,
+}
+
+impl Default for RefreshSettings {
+    fn default() -> Self {
+        RefreshSettings {
+            interval: Duration::minutes(15),
+            airing_max_age: Duration::hours(12),
+            finished_max_age: Duration::days(30),
+            batch_limit: 500,
+        }
+    }
+}
+
+impl RefreshSettings {
+    // Reads REFRESH_INTERVAL_MINUTES, REFRESH_AIRING_HOURS,
+    // REFRESH_FINISHED_DAYS and REFRESH_BATCH_LIMIT, falling back to the defaults
+    pub fn from_env() -> Self {
+        let default = RefreshSettings::default();
+        // Zero or negative values fall back to the default, a zero interval would panic
+        let var = |name: &str| {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.parse::<i64>().ok())
+                .filter(|value| *value > 0)
+        };
+
+        RefreshSettings {
+            interval: var("REFRESH_INTERVAL_MINUTES")
+                .map(Duration::minutes)
+                .unwrap_or(default.interval),
+            airing_max_age: var("REFRESH_AIRING_HOURS")
+                .map(Duration::hours)
+                .unwrap_or(default.airing_max_age),
+            finished_max_age: var("REFRESH_FINISHED_DAYS")
+                .map(Duration::days)
+                .unwrap_or(default.finished_max_age),
+            batch_limit: var("REFRESH_BATCH_LIMIT")
+                .map(|limit| limit as u32)
+                .unwrap_or(default.batch_limit),
+        }
+    }
+}
+
+impl ImporterHandle {
+    // Periodically queues animes whose metadata is older than the configured max age
+    pub fn spawn_refresh(&self, db: Pool<MySql>, settings: RefreshSettings) {
+        let importer = self.clone();
+        let period = settings
+            .interval
+            .to_std()
+            .unwrap_or(std::time::Duration::from_secs(15 * 60));
+
+        tokio::spawn(async move {
+            let mut interval = time::interval(period);
+            loop {
+                interval.tick().await;
+
+                let now = Utc::now().naive_utc();
+                let ids = match get_stale_anime_ids(
+                    &db,
+                    now - settings.airing_max_age,
+                    now - settings.finished_max_age,
+                    settings.batch_limit,
+                )
+                .await
+                {
+                    Ok(ids) => ids,
+                    Err(err) => {
+                        tracing::error!("Failed to get animes to refresh: {:?}", err);
+                        continue;
+                    }
+                };
+
+                if ids.is_empty() {
+                    tracing::trace!("No animes need refreshing");
+                    continue;
+                }
+
+                tracing::info!("Queueing {} stale animes for refresh", ids.len());
+                importer.add_all_anime_only(
+                    ids.into_iter().map(|id| id as u32).collect(),
+                    Lane::Refresh,
+                );
+            }
+        });
+    }
+}
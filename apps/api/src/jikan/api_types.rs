@@ -0,0 +1,75 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JikanImage {
+    pub large_image_url: Option<String>,
+    pub image_url: Option<String>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JikanImages {
+    pub jpg: JikanImage,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JikanRelationEntry {
+    pub mal_id: u32,
+    #[serde(rename = "type")]
+    pub entry_type: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JikanRelation {
+    pub relation: String,
+    pub entry: Vec<JikanRelationEntry>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JikanAired {
+    // ISO 8601 timestamps
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+// Genres, studios and anything else that links to another MAL page
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JikanNamed {
+    pub name: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JikanAnime {
+    pub mal_id: u32,
+    pub title: String,
+    pub title_english: Option<String>,
+    pub title_japanese: Option<String>,
+    #[serde(default)]
+    pub title_synonyms: Vec<String>,
+    // TV, Movie, OVA...
+    #[serde(rename = "type")]
+    pub anime_type: Option<String>,
+    pub status: Option<String>,
+    pub season: Option<String>,
+    pub year: Option<u32>,
+    pub aired: Option<JikanAired>,
+    pub episodes: Option<u32>,
+    // eg. "24 min per ep" or "1 hr 50 min"
+    pub duration: Option<String>,
+    // eg. "PG-13 - Teens 13 or older"
+    pub rating: Option<String>,
+    // Out of 10
+    pub score: Option<f64>,
+    pub members: Option<u32>,
+    pub synopsis: Option<String>,
+    #[serde(default)]
+    pub genres: Vec<JikanNamed>,
+    #[serde(default)]
+    pub studios: Vec<JikanNamed>,
+    pub images: JikanImages,
+    pub relations: Option<Vec<JikanRelation>>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JikanResponse {
+    pub data: JikanAnime,
+}
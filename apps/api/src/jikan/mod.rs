@@ -0,0 +1,252 @@
+pub mod api_types;
+
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use reqwest::{Client, StatusCode};
+
+use self::api_types::{JikanAnime, JikanResponse};
+use crate::metadata::{
+    AnimeMetadata, AnimeRelation, AnimeStudio, MetadataBatch, MetadataError, MetadataProvider,
+};
+use crate::metrics;
+
+// Jikan allows 3 requests a second and 60 a minute
+const JIKAN_REQUEST_DELAY: Duration = Duration::from_millis(1000);
+const MAX_JIKAN_PER_BATCH: usize = 5;
+
+pub enum JikanResult {
+    Found(Box<JikanAnime>),
+    NotFound,
+}
+
+pub async fn get_anime_from_jikan(
+    reqwest: &Client,
+    url: &str,
+    id: u32,
+) -> Result<JikanResult, MetadataError> {
+    let started = Instant::now();
+    let res = reqwest
+        .get(format!("{}/anime/{}/full", url, id))
+        .send()
+        .await;
+    metrics::record_upstream(
+        "jikan",
+        res.as_ref().ok().map(|res| res.status().as_u16()),
+        started,
+    );
+    let res = res.map_err(|e| MetadataError::Request(e.into()))?;
+
+    match res.status() {
+        StatusCode::NOT_FOUND => return Ok(JikanResult::NotFound),
+        StatusCode::TOO_MANY_REQUESTS => {
+            return Err(MetadataError::RateLimited(
+                Utc::now() + chrono::Duration::minutes(1),
+            ))
+        }
+        _ => {}
+    }
+
+    let text = res
+        .text()
+        .await
+        .map_err(|e| MetadataError::Request(e.into()))?;
+    let anime: JikanResponse = serde_json::from_str(&text).map_err(|e| {
+        tracing::error!("Response: {:?}", text);
+        MetadataError::Parse(e.into())
+    })?;
+
+    Ok(JikanResult::Found(Box::new(anime.data)))
+}
+
+// Uses the unofficial MAL api, so it knows about every MAL id
+// but only returns one anime per request
+pub struct JikanProvider {
+    reqwest: Client,
+    url: String,
+}
+
+impl JikanProvider {
+    pub fn new(reqwest: Client, url: String) -> Self {
+        JikanProvider { reqwest, url }
+    }
+}
+
+#[async_trait]
+impl MetadataProvider for JikanProvider {
+    fn name(&self) -> &'static str {
+        "Jikan"
+    }
+
+    fn batch_size(&self) -> usize {
+        MAX_JIKAN_PER_BATCH
+    }
+
+    // Ids that fail are left out of the batch for the importer to retry,
+    // unless none were fetched, then the error is returned as is
+    async fn fetch(&self, ids: Vec<u32>) -> Result<MetadataBatch, MetadataError> {
+        let mut batch = MetadataBatch::default();
+        let mut first_error = None;
+
+        for (i, id) in ids.into_iter().enumerate() {
+            if i > 0 {
+                tokio::time::sleep(JIKAN_REQUEST_DELAY).await;
+            }
+
+            match get_anime_from_jikan(&self.reqwest, &self.url, id).await {
+                Ok(JikanResult::Found(anime)) => batch.animes.push(into_metadata(*anime)),
+                Ok(JikanResult::NotFound) => batch.not_found.push(id),
+                // The rest of the ids would be rate limited too
+                Err(err @ MetadataError::RateLimited(_)) => {
+                    tracing::warn!(anime = id, "Jikan rate limited: {}", err);
+                    first_error.get_or_insert(err);
+                    break;
+                }
+                Err(err) => {
+                    tracing::error!(anime = id, "Failed to get anime from jikan: {}", err);
+                    first_error.get_or_insert(err);
+                }
+            }
+        }
+
+        match first_error {
+            Some(err) if batch.animes.is_empty() && batch.not_found.is_empty() => Err(err),
+            _ => Ok(batch),
+        }
+    }
+}
+
+fn into_metadata(anime: JikanAnime) -> AnimeMetadata {
+    let status = match anime.status.as_deref() {
+        Some("Finished Airing") => "FINISHED",
+        Some("Currently Airing") => "RELEASING",
+        _ => "NOT_YET_RELEASED",
+    };
+
+    let relations = anime
+        .relations
+        .unwrap_or_default()
+        .into_iter()
+        .flat_map(|relation| {
+            let relation_type = relation_type(&relation.relation);
+            relation
+                .entry
+                .into_iter()
+                .filter(|entry| entry.entry_type == "anime")
+                .map(move |entry| AnimeRelation {
+                    id_mal: entry.mal_id,
+                    relation_type: relation_type.to_string(),
+                })
+        })
+        .collect();
+
+    let aired = anime.aired.unwrap_or_default();
+
+    AnimeMetadata {
+        id_mal: anime.mal_id,
+        romaji_title: Some(anime.title),
+        english_title: anime.title_english,
+        native_title: anime.title_japanese,
+        synonyms: anime.title_synonyms,
+        status: status.to_string(),
+        format: anime
+            .anime_type
+            .as_deref()
+            .and_then(format)
+            .map(String::from),
+        picture: anime
+            .images
+            .jpg
+            .large_image_url
+            .or(anime.images.jpg.image_url)
+            .unwrap_or_default(),
+        // Jikan has no banners
+        banner_image: None,
+        description: anime.synopsis,
+        season: anime.season.map(|season| season.to_uppercase()),
+        season_year: anime.year,
+        start_date: aired.from.as_deref().and_then(to_date),
+        end_date: aired.to.as_deref().and_then(to_date),
+        episodes: anime.episodes,
+        duration: anime.duration.as_deref().and_then(duration_minutes),
+        // Jikan only has the weekly broadcast time, the importer works
+        // the episodes out from the one on the MAL list entry
+        airing: vec![],
+        genres: anime.genres.into_iter().map(|genre| genre.name).collect(),
+        // MAL does not have tags like anilist does
+        tags: vec![],
+        // Producers are left out since anilist does not list them either
+        studios: anime
+            .studios
+            .into_iter()
+            .map(|studio| AnimeStudio {
+                name: studio.name,
+                is_main: true,
+            })
+            .collect(),
+        average_score: anime.score.map(|score| (score * 10.0).round() as u32),
+        popularity: anime.members,
+        is_adult: anime.rating.is_some_and(|rating| rating.starts_with("Rx")),
+        relations,
+    }
+}
+
+// Maps MAL anime types onto anilist's formats
+fn format(anime_type: &str) -> Option<&'static str> {
+    match anime_type {
+        "TV" => Some("TV"),
+        "Movie" => Some("MOVIE"),
+        "OVA" => Some("OVA"),
+        "ONA" => Some("ONA"),
+        "Special" | "TV Special" => Some("SPECIAL"),
+        "Music" => Some("MUSIC"),
+        _ => None,
+    }
+}
+
+fn to_date(timestamp: &str) -> Option<NaiveDate> {
+    DateTime::parse_from_rfc3339(timestamp)
+        .ok()
+        .map(|date| date.date_naive())
+}
+
+// Parses durations like "24 min per ep" and "1 hr 50 min"
+fn duration_minutes(duration: &str) -> Option<u32> {
+    let mut minutes = 0;
+    let mut words = duration.split_whitespace().peekable();
+    while let Some(word) = words.next() {
+        let Ok(value) = word.parse::<u32>() else {
+            continue;
+        };
+        match words.peek().copied() {
+            Some("hr") | Some("hrs") => minutes += value * 60,
+            Some("min") | Some("mins") => minutes += value,
+            _ => {}
+        }
+    }
+
+    if minutes == 0 {
+        return None;
+    }
+    Some(minutes)
+}
+
+// Maps MAL relation names onto anilist's relation types
+fn relation_type(relation: &str) -> &'static str {
+    match relation {
+        "Prequel" => "PREQUEL",
+        "Sequel" => "SEQUEL",
+        "Side Story" | "Side story" => "SIDE_STORY",
+        "Spin-Off" | "Spin-off" => "SPIN_OFF",
+        "Alternative Version"
+        | "Alternative version"
+        | "Alternative Setting"
+        | "Alternative setting" => "ALTERNATIVE",
+        "Parent Story" | "Parent story" | "Full Story" | "Full story" => "PARENT",
+        "Summary" => "SUMMARY",
+        "Character" => "CHARACTER",
+        "Adaptation" => "ADAPTATION",
+        _ => "OTHER",
+    }
+}
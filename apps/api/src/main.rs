@@ -1,57 +1,69 @@
 mod anilist;
 mod auth;
+mod calendar;
+mod config;
 mod consts;
 mod helpers;
 mod importer;
+mod jikan;
 mod mal;
+mod metadata;
+mod metrics;
 mod middleware;
 mod models;
 mod routes;
+mod series;
+#[cfg(test)]
+mod tests;
 use std::{
     fmt::{self, Display, Formatter},
     net::SocketAddr,
     sync::Arc,
-    time::Duration,
 };
 
 use axum::{
     extract::{FromRef, State},
     http::{HeaderValue, Method, StatusCode},
-    middleware::from_fn_with_state,
+    middleware::{from_fn, from_fn_with_state},
     response::{IntoResponse, Response},
-    routing::{get, post},
+    routing::{delete, get, patch, post},
     Extension, Json, Router,
 };
 use axum_extra::extract::cookie::Key;
 use dotenvy::dotenv;
 use helpers::json_response;
+use oauth2::basic::BasicClient;
 use reqwest::Client;
 use serde_json::json;
 use sqlx::mysql::MySqlPoolOptions;
-use tokio::{sync::Mutex, time};
 use tower_http::cors::{AllowHeaders, AllowOrigin, CorsLayer};
 use tower_http::services::ServeDir;
 
-use crate::{auth::oauth::create_oauth_client, importer::Importer, middleware::auth_guard::guard};
+use crate::{
+    anilist::{provider::AniListProvider, DEFAULT_ANILIST_PER_QUERY},
+    auth::oauth::create_oauth_client,
+    config::UpstreamUrls,
+    importer::{CrawlSettings, Importer, ImporterHandle, Lane, RefreshSettings},
+    jikan::JikanProvider,
+    mal::outbox::spawn_mal_outbox,
+    metadata::FallbackProvider,
+    middleware::{admin_guard::admin_guard, auth_guard::guard},
+};
 
 #[axum::debug_handler]
 async fn debug_route(State(state): State<AppState>) -> impl IntoResponse {
-    let importer = state.importer.lock().await;
     json_response!(StatusCode::OK, {
-        "queue": importer.stats()
+        "queue": state.importer.stats().await
     })
 }
 
 #[axum::debug_handler]
 async fn test_handler(State(state): State<AppState>) -> impl IntoResponse {
-    let mut importer = state.importer.lock().await;
-    importer.add_anime_only(2025);
-    // importer.add_anime_only(36098);
-    // importer.add_anime_only(59226);
-    // importer.add_anime_only(59027);
+    state.importer.add_all_anime_only(vec![2025], Lane::User);
+    // state.importer.add_all_anime_only(vec![36098, 59226, 59027]);
 
     json_response!(StatusCode::OK, {
-        "queue": importer.stats()
+        "queue": state.importer.stats().await
     })
 }
 
@@ -60,7 +72,13 @@ pub struct AppState {
     key: Key,
     db: sqlx::Pool<sqlx::MySql>,
     reqwest: Client,
-    importer: Arc<Mutex<Importer>>,
+    importer: ImporterHandle,
+    admin_mal_ids: Vec<i32>,
+    urls: UpstreamUrls,
+    // Where the api is publicly reachable, for links outside of the web app
+    api_url: String,
+    // Also decides which animes on a synced list are stale enough to import again
+    refresh: RefreshSettings,
 }
 
 impl FromRef<AppState> for sqlx::Pool<sqlx::MySql> {
@@ -88,17 +106,15 @@ async fn main() {
 
     tracing::info!("Starting server...");
 
-    let cors = CorsLayer::new()
-        .allow_methods([Method::GET, Method::POST])
-        .allow_credentials(true)
-        .allow_headers(AllowHeaders::mirror_request())
-        .allow_origin(AllowOrigin::exact(HeaderValue::from_static(
-            "http://localhost:3000",
-        )));
-
     let api_url = std::env::var("API_URL").unwrap_or("http://localhost:3001".to_string());
     let mal_client_id = std::env::var("MAL_CLIENT_ID").expect("MAL_CLIENT_ID not set");
     let mal_client_secret = std::env::var("MAL_CLIENT_SECRET").expect("MAL_CLIENT_SECRET not set");
+    // Comma separated list of MAL user ids allowed to use the admin routes
+    let admin_mal_ids = std::env::var("ADMIN_MAL_IDS")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|id| id.trim().parse::<i32>().ok())
+        .collect();
 
     let db_url = std::env::var("DATABASE_URL").expect("DATABASE_URL not set");
     let db_pool = MySqlPoolOptions::new()
@@ -107,30 +123,65 @@ async fn main() {
         .await
         .expect("Failed to connect to database");
 
+    let urls = UpstreamUrls::from_env();
     let reqwest = Client::new();
-    let importer = Arc::new(Mutex::new(Importer::new(reqwest.clone(), db_pool.clone())));
+    let anilist_batch_size = std::env::var("ANILIST_BATCH_SIZE")
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .filter(|size| *size > 0)
+        .unwrap_or(DEFAULT_ANILIST_PER_QUERY);
+    let provider = FallbackProvider::new(
+        AniListProvider::new(reqwest.clone(), urls.anilist.clone(), anilist_batch_size),
+        JikanProvider::new(reqwest.clone(), urls.jikan.clone()),
+    );
+    let mut importer = Importer::new(
+        Arc::new(provider),
+        db_pool.clone(),
+        CrawlSettings::from_env(),
+    );
+    importer.resume().await;
+    let importer = importer.spawn();
+    let refresh = RefreshSettings::from_env();
+    importer.spawn_refresh(db_pool.clone(), refresh.clone());
 
     let state = AppState {
         key: Key::generate(),
         db: db_pool,
         reqwest,
-        importer: importer.clone(),
+        importer,
+        admin_mal_ids,
+        urls: urls.clone(),
+        api_url: api_url.clone(),
+        refresh,
     };
 
-    tokio::spawn(async move {
-        let mut interval = time::interval(Duration::from_millis(2000));
+    let oauth_client = create_oauth_client(
+        api_url.clone(),
+        urls.mal_oauth.clone(),
+        mal_client_id.clone(),
+        mal_client_secret,
+    );
+    spawn_mal_outbox(state.clone(), oauth_client.clone());
+    let app = create_router(state, oauth_client);
 
-        loop {
-            interval.tick().await;
-            let mut importer_ref = importer.lock().await;
-            importer_ref.process().await;
-        }
-    });
+    let address = SocketAddr::from(([0, 0, 0, 0], 3001));
+    let listener = tokio::net::TcpListener::bind(address).await.unwrap();
+    tracing::info!("listening on {}", address);
+    axum::serve(listener, app.into_make_service())
+        .await
+        .unwrap();
+}
 
-    let oauth_client =
-        create_oauth_client(api_url.clone(), mal_client_id.clone(), mal_client_secret);
+pub fn create_router(state: AppState, oauth_client: BasicClient) -> Router {
+    let cors = CorsLayer::new()
+        .allow_methods([Method::GET, Method::POST, Method::PATCH, Method::DELETE])
+        .allow_credentials(true)
+        .allow_headers(AllowHeaders::mirror_request())
+        .allow_origin(AllowOrigin::exact(HeaderValue::from_static(
+            "http://localhost:3000",
+        )));
 
-    let app = Router::new()
+    Router::new()
         .nest_service("/", ServeDir::new("public"))
         .nest(
             "/api/v1",
@@ -140,6 +191,38 @@ async fn main() {
                 .route("/auth/me", get(routes::user::get_user))
                 .route("/user/list", get(routes::user::get_list))
                 .route("/user/list", post(routes::user::update_list_order))
+                .route(
+                    "/user/list/:anime_id",
+                    patch(routes::user::update_list_entry),
+                )
+                .route("/user/import/status", get(routes::user::get_import_status))
+                .route("/user/schedule", get(routes::user::get_schedule))
+                .route("/user/calendar", get(routes::calendar::get_calendar))
+                .route("/user/calendar", post(routes::calendar::create_calendar))
+                .route("/user/calendar", delete(routes::calendar::revoke_calendar))
+                .route(
+                    "/user/import/events",
+                    get(routes::user::import_status_events),
+                )
+                .nest(
+                    "/admin",
+                    Router::new()
+                        .route("/ignored", get(routes::admin::get_ignored))
+                        .route("/ignored", delete(routes::admin::clear_ignored))
+                        .route("/ignored/:id", delete(routes::admin::clear_ignored_anime))
+                        .route("/queue/:id", delete(routes::admin::cancel_queued_anime))
+                        .route("/discovered", get(routes::admin::get_discovered))
+                        .route("/dead-letter", get(routes::admin::get_dead_letter))
+                        .route(
+                            "/dead-letter/requeue",
+                            post(routes::admin::requeue_dead_letter),
+                        )
+                        .route(
+                            "/dead-letter/:id/requeue",
+                            post(routes::admin::requeue_dead_letter_anime),
+                        )
+                        .route_layer(from_fn_with_state(state.clone(), admin_guard)),
+                )
                 // .route("/order", post(routes::anime::update_list_order))
                 .route_layer(from_fn_with_state(state.clone(), guard))
                 // .route("/anime/:id", get(routes::anime::get_anime))
@@ -161,16 +244,15 @@ async fn main() {
             "/oauth/mal/callback",
             get(routes::auth::handle_mal_callback),
         )
+        .route(
+            "/calendar/:file",
+            get(routes::calendar::get_calendar_feed_ics),
+        )
+        .route("/metrics", get(metrics::metrics_handler))
+        .layer(from_fn(metrics::track_requests))
         .layer(Extension(oauth_client))
         .layer(cors)
-        .with_state(state.clone());
-
-    let address = SocketAddr::from(([0, 0, 0, 0], 3001));
-    let listener = tokio::net::TcpListener::bind(address).await.unwrap();
-    tracing::info!("listening on {}", address);
-    axum::serve(listener, app.into_make_service())
-        .await
-        .unwrap();
+        .with_state(state)
 }
 
 // Make our own error that wraps `anyhow::Error`.
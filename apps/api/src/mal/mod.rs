@@ -1,9 +1,16 @@
-use anyhow::Context;
-use reqwest::Client;
+pub mod outbox;
+pub mod sync;
+pub mod token;
+
+use std::time::Instant;
+
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
+use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
 
-use crate::models::user::DBUser;
+use crate::importer::{AnimeUserEntry, AnimeWatchStatus, UserListStatus};
+use crate::metrics;
 
 #[derive(Deserialize, Serialize, Clone)]
 pub struct AnimePicture {
@@ -26,20 +33,23 @@ pub struct AnimeListNode {
     pub broadcast: Option<AnimeBroadcast>,
 }
 
-#[derive(Deserialize, Serialize, Clone)]
-#[serde(rename_all = "snake_case")]
-pub enum MalListStatusStatus {
-    Watching,
-    Completed,
-    OnHold,
-    Dropped,
-    PlanToWatch,
-}
-
 #[derive(Deserialize, Serialize, Clone)]
 pub struct MalListStatus {
     pub status: String,
     pub score: i32,
+    #[serde(default)]
+    pub num_episodes_watched: i32,
+    #[serde(default)]
+    pub is_rewatching: bool,
+    // "YYYY-MM-DD", or just the year and month or year when that is all the user set
+    pub start_date: Option<String>,
+    pub finish_date: Option<String>,
+    // 0 = low, 1 = medium, 2 = high
+    #[serde(default)]
+    pub priority: i32,
+    #[serde(default)]
+    pub comments: String,
+    pub updated_at: Option<DateTime<FixedOffset>>,
 }
 
 #[derive(Deserialize, Serialize, Clone)]
@@ -48,32 +58,233 @@ pub struct AnimeListItem {
     pub list_status: MalListStatus,
 }
 
+// Only full dates are stored
+fn parse_list_date(date: &Option<String>) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date.as_deref()?, "%Y-%m-%d").ok()
+}
+
+impl AnimeListItem {
+    pub fn user_entry(&self, user_id: &str) -> AnimeUserEntry {
+        let list_status = &self.list_status;
+        AnimeUserEntry {
+            anime_id: self.node.id,
+            user_id: user_id.to_string(),
+            status: list_status
+                .status
+                .parse::<AnimeWatchStatus>()
+                .unwrap_or(AnimeWatchStatus::Watching),
+            list_status: UserListStatus {
+                score: list_status.score.max(0) as u32,
+                num_episodes_watched: list_status.num_episodes_watched.max(0) as u32,
+                is_rewatching: list_status.is_rewatching,
+                start_date: parse_list_date(&list_status.start_date),
+                finish_date: parse_list_date(&list_status.finish_date),
+                priority: list_status.priority.max(0) as u32,
+                comments: list_status.comments.clone(),
+                updated_at: list_status
+                    .updated_at
+                    .map(|updated_at| updated_at.with_timezone(&Utc).naive_utc()),
+            },
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct MalPaging {
+    // Urls of the neighbouring pages, missing on the first and last page
+    pub previous: Option<String>,
+    pub next: Option<String>,
+}
+
+// A single page of a users list
 #[derive(Deserialize, Serialize, Clone)]
 pub struct MalAnimeListResponse {
     pub data: Vec<AnimeListItem>,
-    pub paging: Value,
+    #[serde(default)]
+    pub paging: MalPaging,
 }
-pub async fn get_mal_user_list(
+
+// Every page of a users list that could be fetched
+pub struct MalAnimeList {
+    pub data: Vec<AnimeListItem>,
+    // Set when a page after the first failed, `data` only has the pages before it
+    pub error: Option<anyhow::Error>,
+}
+
+// MAL rejected the access token, it has expired or been revoked
+#[derive(Debug)]
+pub struct MalUnauthorized;
+
+impl std::fmt::Display for MalUnauthorized {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MAL rejected the access token")
+    }
+}
+
+impl std::error::Error for MalUnauthorized {}
+
+// MAL refused a change for a reason retrying will not fix, eg. the anime does not exist
+#[derive(Debug)]
+pub struct MalRejected(pub StatusCode, pub String);
+
+impl std::fmt::Display for MalRejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MAL rejected the change with {}: {}", self.0, self.1)
+    }
+}
+
+impl std::error::Error for MalRejected {}
+
+// Fields of a list entry to change, the rest are left as they are
+#[derive(Debug, Clone, Default)]
+pub struct MalListStatusUpdate {
+    pub status: Option<AnimeWatchStatus>,
+    // 0 to 10, 0 removes the score
+    pub score: Option<u32>,
+    pub num_episodes_watched: Option<u32>,
+}
+
+pub async fn update_mal_list_status(
     reqwest: Client,
-    user: DBUser,
-) -> Result<MalAnimeListResponse, anyhow::Error> {
-    tracing::info!("Getting MAL anime list for user {}", user.id);
+    mal_api_url: &str,
+    access_token: String,
+    anime_id: u32,
+    update: &MalListStatusUpdate,
+) -> Result<(), anyhow::Error> {
+    let mut form: Vec<(&str, String)> = vec![];
+    if let Some(status) = update.status.clone() {
+        form.push(("status", status.into()));
+    }
+    if let Some(score) = update.score {
+        form.push(("score", score.to_string()));
+    }
+    if let Some(episodes) = update.num_episodes_watched {
+        // Named differently to the field MAL returns
+        form.push(("num_watched_episodes", episodes.to_string()));
+    }
+
+    let started = Instant::now();
     let res = reqwest
-        .get("https://api.myanimelist.net/v2/users/@me/animelist?fields=list_status,node.status,node.num_episodes,node.broadcast&limit=1000&nsfw=1")
-        .bearer_auth(user.mal_access_token)
+        .patch(format!("{}/anime/{}/my_list_status", mal_api_url, anime_id))
+        .bearer_auth(access_token)
+        .form(&form)
         .send()
-        .await
-        .expect("Failed to get MAL anime");
+        .await;
+    metrics::record_upstream(
+        "mal",
+        res.as_ref().ok().map(|res| res.status().as_u16()),
+        started,
+    );
+    let res = res.context("Failed to update MAL list status")?;
+
+    let status = res.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    if status == StatusCode::UNAUTHORIZED {
+        return Err(MalUnauthorized.into());
+    }
+
+    let text = res.text().await.unwrap_or_default();
+    if status.is_client_error() && status != StatusCode::TOO_MANY_REQUESTS {
+        return Err(MalRejected(status, text).into());
+    }
+
+    Err(anyhow!(
+        "MAL returned {} for list status update: {}",
+        status,
+        text
+    ))
+}
+
+// Most entries MAL returns in a page
+const MAL_LIST_PAGE_SIZE: u32 = 1000;
+
+// priority and comments are only sent when asked for by name
+const MAL_LIST_FIELDS: &str = "list_status{status,score,num_episodes_watched,is_rewatching,\
+start_date,finish_date,priority,comments,updated_at},node.status,node.num_episodes,node.broadcast";
 
+// Stops a `next` that never runs out from paging forever
+const MAX_MAL_LIST_PAGES: usize = 100;
+
+async fn get_mal_list_page(
+    reqwest: &Client,
+    url: &str,
+    token: &str,
+) -> Result<MalAnimeListResponse, anyhow::Error> {
+    let started = Instant::now();
+    let res = reqwest.get(url).bearer_auth(token).send().await;
+    metrics::record_upstream(
+        "mal",
+        res.as_ref().ok().map(|res| res.status().as_u16()),
+        started,
+    );
+    let res = res.context("Failed to get MAL anime list")?;
+
+    let status = res.status();
+    if status == StatusCode::UNAUTHORIZED {
+        return Err(MalUnauthorized.into());
+    }
     let text = res.text().await?;
-    let anime: MalAnimeListResponse = serde_json::from_str(&text)
+    if !status.is_success() {
+        return Err(anyhow!("MAL returned {} for anime list: {}", status, text));
+    }
+
+    let page: MalAnimeListResponse = serde_json::from_str(&text)
         .with_context(|| format!("Unable to deserialise response. Body was: \"{}\"", text))?;
-    let paging = anime.paging.clone();
 
-    tracing::info!("Got {} anime from MAL", anime.data.len());
+    Ok(page)
+}
+
+// Fetches the whole list of the user `access_token` belongs to, following
+// `paging.next` until the last page. Only fails if the first page does
+pub async fn get_mal_user_list(
+    reqwest: Client,
+    mal_api_url: &str,
+    access_token: String,
+) -> Result<MalAnimeList, anyhow::Error> {
+    tracing::info!("Getting MAL anime list");
+
+    let mut url = format!(
+        "{}/users/@me/animelist?fields={}&limit={}&nsfw=1",
+        mal_api_url, MAL_LIST_FIELDS, MAL_LIST_PAGE_SIZE
+    );
+    let mut data = vec![];
+    let mut pages = 0;
+    loop {
+        let page = match get_mal_list_page(&reqwest, &url, &access_token).await {
+            Ok(page) => page,
+            Err(err) if pages == 0 => return Err(err),
+            Err(err) => {
+                return Ok(MalAnimeList {
+                    data,
+                    error: Some(err.context(format!("Failed to get page {}", pages + 1))),
+                });
+            }
+        };
+        pages += 1;
+        data.extend(page.data);
+
+        let Some(next) = page.paging.next else {
+            break;
+        };
+        // The access token is sent to `next`, so it has to be MAL
+        if !next.starts_with(mal_api_url) {
+            return Ok(MalAnimeList {
+                data,
+                error: Some(anyhow!("Next page {} is not on {}", next, mal_api_url)),
+            });
+        }
+        if pages >= MAX_MAL_LIST_PAGES {
+            return Ok(MalAnimeList {
+                data,
+                error: Some(anyhow!("Gave up after {} pages", pages)),
+            });
+        }
+        url = next;
+    }
+
+    tracing::info!("Got {} anime from MAL in {} pages", data.len(), pages);
 
-    Ok(MalAnimeListResponse {
-        data: anime.data,
-        paging,
-    })
+    Ok(MalAnimeList { data, error: None })
 }
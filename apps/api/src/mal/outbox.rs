@@ -0,0 +1,173 @@
+// Sends list changes made in sei to MAL. Changes are queued in mal_list_updates
+// first so ones MAL could not take, eg. while it is down, are retried later
+
+use std::time::Duration as StdDuration;
+
+use chrono::{Duration, Utc};
+use oauth2::basic::BasicClient;
+use serde::Serialize;
+use tokio::time;
+
+use super::token::with_mal_token;
+use super::{update_mal_list_status, MalRejected};
+use crate::models::mal_list_updates::{
+    complete_mal_list_update, delete_mal_list_update, get_due_mal_list_updates,
+    get_mal_list_update, retry_mal_list_update,
+};
+use crate::models::user::{get_user, DBUser};
+use crate::AppState;
+
+// How often queued changes are checked for ones due to be retried
+const OUTBOX_INTERVAL_SECS: u64 = 60;
+
+// Most changes sent each time the queue is checked
+const OUTBOX_BATCH_LIMIT: u32 = 50;
+
+// Wait before the first retry, doubled after every failure up to the max
+const RETRY_BASE_SECS: i64 = 30;
+const RETRY_MAX_SECS: i64 = 6 * 60 * 60;
+
+// Changes that failed this many times are dropped
+const MAX_PUSH_ATTEMPTS: i32 = 8;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PushOutcome {
+    // MAL has the change
+    Synced,
+    // MAL could not be reached, the change will be retried
+    Queued,
+    // MAL refused the change, or it failed too many times, and it was dropped
+    Rejected,
+}
+
+fn retry_delay(attempts: i32) -> Duration {
+    let secs = RETRY_BASE_SECS.saturating_mul(1_i64 << attempts.clamp(0, 20));
+    Duration::seconds(secs.min(RETRY_MAX_SECS))
+}
+
+// Sends the users queued change to `anime_id`, if there is one
+pub async fn push_mal_list_update(
+    state: &AppState,
+    oauth_client: &BasicClient,
+    user: &mut DBUser,
+    anime_id: u32,
+) -> Result<PushOutcome, anyhow::Error> {
+    let Some(queued) = get_mal_list_update(&state.db, &user.id, anime_id).await? else {
+        return Ok(PushOutcome::Synced);
+    };
+
+    let update = queued.update();
+    let result = with_mal_token(&state.db, oauth_client, user, |token| {
+        update_mal_list_status(
+            state.reqwest.clone(),
+            &state.urls.mal_api,
+            token,
+            anime_id,
+            &update,
+        )
+    })
+    .await;
+
+    match result {
+        Ok(()) => {
+            complete_mal_list_update(&state.db, &queued).await?;
+            Ok(PushOutcome::Synced)
+        }
+        Err(err) if err.is::<MalRejected>() => {
+            tracing::warn!(
+                "MAL rejected list update of {} for user {}: {}",
+                anime_id,
+                user.id,
+                err
+            );
+            delete_mal_list_update(&state.db, &user.id, queued.anime_id).await?;
+            Ok(PushOutcome::Rejected)
+        }
+        Err(err) if queued.attempts + 1 >= MAX_PUSH_ATTEMPTS => {
+            tracing::error!(
+                "Giving up on list update of {} for user {} after {} attempts: {:?}",
+                anime_id,
+                user.id,
+                MAX_PUSH_ATTEMPTS,
+                err
+            );
+            delete_mal_list_update(&state.db, &user.id, queued.anime_id).await?;
+            Ok(PushOutcome::Rejected)
+        }
+        Err(err) => {
+            let next_attempt_at = (Utc::now() + retry_delay(queued.attempts)).naive_utc();
+            tracing::warn!(
+                "Failed to send list update of {} for user {}, retrying at {}: {:?}",
+                anime_id,
+                user.id,
+                next_attempt_at,
+                err
+            );
+            retry_mal_list_update(
+                &state.db,
+                &user.id,
+                queued.anime_id,
+                next_attempt_at,
+                err.to_string(),
+            )
+            .await?;
+            Ok(PushOutcome::Queued)
+        }
+    }
+}
+
+pub fn spawn_mal_outbox(state: AppState, oauth_client: BasicClient) {
+    tokio::spawn(async move {
+        let mut interval = time::interval(StdDuration::from_secs(OUTBOX_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+
+            let due = match get_due_mal_list_updates(&state.db, OUTBOX_BATCH_LIMIT).await {
+                Ok(due) => due,
+                Err(err) => {
+                    tracing::error!("Failed to get queued MAL list updates: {:?}", err);
+                    continue;
+                }
+            };
+
+            if due.is_empty() {
+                tracing::trace!("No MAL list updates to send");
+                continue;
+            }
+
+            tracing::info!("Sending {} queued MAL list updates", due.len());
+            for queued in due {
+                let mut user = match get_user(&state.db, &queued.user_id).await {
+                    Ok(Some(user)) => user,
+                    Ok(None) => {
+                        // The user was deleted, there is no one to send it for
+                        if let Err(err) =
+                            delete_mal_list_update(&state.db, &queued.user_id, queued.anime_id)
+                                .await
+                        {
+                            tracing::error!("Failed to drop MAL list update: {:?}", err);
+                        }
+                        continue;
+                    }
+                    Err(err) => {
+                        tracing::error!("Failed to get user for MAL list update: {:?}", err);
+                        continue;
+                    }
+                };
+
+                // Flagged since the queue was read, left until they log in again
+                if user.mal_relogin_required {
+                    continue;
+                }
+
+                if let Err(err) =
+                    push_mal_list_update(&state, &oauth_client, &mut user, queued.anime_id as u32)
+                        .await
+                {
+                    tracing::error!("Failed to send MAL list update: {:?}", err);
+                }
+            }
+        }
+    });
+}
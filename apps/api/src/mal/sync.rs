@@ -0,0 +1,136 @@
+// Brings a users entries in anime_users in line with their MAL list. Entries are
+// compared by when they last changed on MAL, so unchanged ones are left alone
+
+use std::collections::{HashMap, HashSet};
+
+use chrono::Utc;
+use serde::Serialize;
+
+use super::MalAnimeList;
+use crate::importer::AnimeUserEntry;
+use crate::models::airing_schedules::{upsert_anime_broadcasts, DBAnimeBroadcast};
+use crate::models::anime::get_fresh_anime_ids;
+use crate::models::anime_users::{get_all_user_entries, link_user_to_anime, remove_user_entries};
+use crate::models::mal_list_updates::get_pending_mal_list_update_ids;
+use crate::models::user::set_list_last_update;
+use crate::AppState;
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct MalSyncSummary {
+    // New or changed entries stored straight away
+    pub linked: usize,
+    // New or changed entries of animes that had to be imported first
+    pub queued: usize,
+    pub unchanged: usize,
+    // Entries no longer on the MAL list
+    pub removed: usize,
+}
+
+pub async fn sync_mal_list(
+    state: &AppState,
+    user_id: &str,
+    mal: MalAnimeList,
+) -> Result<MalSyncSummary, anyhow::Error> {
+    let mut summary = MalSyncSummary::default();
+
+    let existing: HashMap<u32, _> = get_all_user_entries(&state.db, user_id)
+        .await?
+        .into_iter()
+        .map(|entry| (entry.anime_id as u32, entry.mal_updated_at))
+        .collect();
+    // Entries with changes still to be sent to MAL are newer here
+    let pending: HashSet<u32> = get_pending_mal_list_update_ids(&state.db, user_id)
+        .await?
+        .into_iter()
+        .map(|id| id as u32)
+        .collect();
+
+    let mut changed: Vec<AnimeUserEntry> = vec![];
+    for item in &mal.data {
+        if pending.contains(&item.node.id) {
+            continue;
+        }
+
+        let entry = item.user_entry(user_id);
+        match (existing.get(&entry.anime_id), entry.list_status.updated_at) {
+            (Some(Some(stored)), Some(updated_at)) if *stored == updated_at => {
+                summary.unchanged += 1;
+            }
+            _ => changed.push(entry),
+        }
+    }
+
+    // Kept for animes anilist has no airing schedule for, see weekly_airing.
+    // Stored first so the importer has them for the animes queued below
+    let broadcasts: Vec<DBAnimeBroadcast> = mal
+        .data
+        .iter()
+        .filter_map(|item| {
+            let broadcast = item.node.broadcast.as_ref()?;
+            Some(DBAnimeBroadcast {
+                anime_id: item.node.id as i32,
+                day_of_the_week: broadcast.day_of_the_week.clone()?,
+                start_time: broadcast.start_time.clone()?,
+            })
+        })
+        .collect();
+    upsert_anime_broadcasts(&state.db, broadcasts).await?;
+
+    // Animes with up to date metadata are linked now, the rest are
+    // imported first and linked by the importer once that is done
+    let now = Utc::now().naive_utc();
+    let changed_ids: Vec<u32> = changed.iter().map(|entry| entry.anime_id).collect();
+    let fresh: HashSet<u32> = get_fresh_anime_ids(
+        &state.db,
+        &changed_ids,
+        now - state.refresh.airing_max_age,
+        now - state.refresh.finished_max_age,
+    )
+    .await?
+    .into_iter()
+    .map(|id| id as u32)
+    .collect();
+    let (linked, queued): (Vec<_>, Vec<_>) = changed
+        .into_iter()
+        .partition(|entry| fresh.contains(&entry.anime_id));
+
+    summary.linked = linked.len();
+    summary.queued = queued.len();
+    link_user_to_anime(
+        &state.db,
+        linked
+            .into_iter()
+            .map(|entry| (entry.anime_id, vec![entry]))
+            .collect(),
+    )
+    .await?;
+    state.importer.add_all(queued);
+
+    // A list missing pages would look like every entry on them had been removed
+    match &mal.error {
+        Some(err) => {
+            tracing::error!(
+                "Only got {} anime of the MAL list for user {}, not removing entries: {:?}",
+                mal.data.len(),
+                user_id,
+                err
+            );
+        }
+        None => {
+            let on_mal: HashSet<u32> = mal.data.iter().map(|item| item.node.id).collect();
+            let removed: Vec<u32> = existing
+                .keys()
+                .filter(|id| !on_mal.contains(id) && !pending.contains(id))
+                .copied()
+                .collect();
+            summary.removed = removed.len();
+            remove_user_entries(&state.db, user_id, &removed).await?;
+        }
+    }
+
+    set_list_last_update(&state.db, user_id).await?;
+
+    tracing::info!("Synced MAL list for user {}: {:?}", user_id, summary);
+
+    Ok(summary)
+}
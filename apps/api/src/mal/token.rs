@@ -0,0 +1,129 @@
+use std::future::Future;
+
+use anyhow::anyhow;
+use chrono::{Duration, NaiveDateTime, Utc};
+use oauth2::basic::{BasicClient, BasicTokenResponse};
+use oauth2::reqwest::async_http_client;
+use oauth2::{RefreshToken, RequestTokenError, TokenResponse};
+use sqlx::{MySql, Pool};
+
+use super::MalUnauthorized;
+use crate::models::user::{flag_mal_relogin, get_user, update_mal_tokens, DBUser};
+
+// Tokens this close to expiring are refreshed before being used
+const REFRESH_BEFORE_EXPIRY_DAYS: i64 = 1;
+
+pub fn token_expires_at(token: &BasicTokenResponse) -> Option<NaiveDateTime> {
+    let expires_in = Duration::from_std(token.expires_in()?).ok()?;
+    Some((Utc::now() + expires_in).naive_utc())
+}
+
+// Swaps the users refresh token for a new access token. If MAL refuses the
+// refresh token the user is flagged, since only logging in again can fix it
+pub async fn refresh_mal_token(
+    db: &Pool<MySql>,
+    oauth_client: &BasicClient,
+    user: &mut DBUser,
+) -> Result<(), anyhow::Error> {
+    tracing::info!("Refreshing MAL token for user {}", user.id);
+
+    // Users from before refresh tokens were stored
+    if user.mal_refresh_token.is_empty() {
+        flag_mal_relogin(db, &user.id).await?;
+        user.mal_relogin_required = true;
+        return Err(anyhow!("User {} has no MAL refresh token", user.id));
+    }
+
+    let token = oauth_client
+        .exchange_refresh_token(&RefreshToken::new(user.mal_refresh_token.clone()))
+        .request_async(async_http_client)
+        .await;
+
+    let token = match token {
+        Ok(token) => token,
+        // MAL turned the refresh token down, eg. invalid_grant
+        Err(RequestTokenError::ServerResponse(response)) => {
+            // MAL replaces the refresh token on every refresh, so another request
+            // for this user may have refreshed it first and made ours stale
+            if let Some(stored) = get_user(db, &user.id).await? {
+                if !stored.mal_refresh_token.is_empty()
+                    && stored.mal_refresh_token != user.mal_refresh_token
+                {
+                    tracing::info!("MAL token for user {} was already refreshed", user.id);
+                    *user = stored;
+                    return Ok(());
+                }
+            }
+
+            tracing::error!(
+                "MAL refused to refresh token for user {}: {:?}",
+                user.id,
+                response
+            );
+            flag_mal_relogin(db, &user.id).await?;
+            user.mal_relogin_required = true;
+            return Err(anyhow!(
+                "MAL refused to refresh token: {}",
+                response.error()
+            ));
+        }
+        // MAL could not be reached or sent something odd, the refresh can be tried again
+        Err(err) => {
+            tracing::error!(
+                "Failed to refresh MAL token for user {}: {:?}",
+                user.id,
+                err
+            );
+            return Err(anyhow!("Failed to refresh MAL token: {}", err));
+        }
+    };
+
+    user.mal_access_token = token.access_token().secret().to_string();
+    // MAL sends a new refresh token, keep the old one if it ever does not
+    if let Some(refresh_token) = token.refresh_token() {
+        user.mal_refresh_token = refresh_token.secret().to_string();
+    }
+    user.mal_token_expires_at = token_expires_at(&token);
+    user.mal_relogin_required = false;
+
+    update_mal_tokens(
+        db,
+        &user.id,
+        &user.mal_access_token,
+        &user.mal_refresh_token,
+        user.mal_token_expires_at,
+    )
+    .await
+}
+
+// Runs `request` with the users access token. The token is refreshed first when
+// it is about to expire, and `request` is retried once with a new token if MAL rejects it
+pub async fn with_mal_token<T, F, Fut>(
+    db: &Pool<MySql>,
+    oauth_client: &BasicClient,
+    user: &mut DBUser,
+    request: F,
+) -> Result<T, anyhow::Error>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<T, anyhow::Error>>,
+{
+    if user.mal_relogin_required {
+        return Err(anyhow!("User {} needs to log in to MAL again", user.id));
+    }
+
+    let refresh_after = (Utc::now() + Duration::days(REFRESH_BEFORE_EXPIRY_DAYS)).naive_utc();
+    if let Some(expires_at) = user.mal_token_expires_at {
+        if expires_at < refresh_after {
+            refresh_mal_token(db, oauth_client, user).await?;
+        }
+    }
+
+    match request(user.mal_access_token.clone()).await {
+        Err(err) if err.is::<MalUnauthorized>() => {
+            refresh_mal_token(db, oauth_client, user).await?;
+            request(user.mal_access_token.clone()).await
+        }
+        result => result,
+    }
+}
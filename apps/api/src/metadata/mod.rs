@@ -0,0 +1,284 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, Utc, Weekday};
+use chrono_tz::Asia::Tokyo;
+
+// Provider neutral anime record, everything the importer stores comes from here
+#[derive(Debug, Clone)]
+pub struct AnimeMetadata {
+    pub id_mal: u32,
+    pub romaji_title: Option<String>,
+    pub english_title: Option<String>,
+    pub native_title: Option<String>,
+    pub synonyms: Vec<String>,
+    // One of the AiringStatus values in the schema
+    pub status: String,
+    // One of the MediaFormat values in the schema
+    pub format: Option<String>,
+    pub picture: String,
+    pub banner_image: Option<String>,
+    pub description: Option<String>,
+    pub season: Option<String>,
+    pub season_year: Option<u32>,
+    pub start_date: Option<NaiveDate>,
+    pub end_date: Option<NaiveDate>,
+    pub episodes: Option<u32>,
+    // Minutes per episode
+    pub duration: Option<u32>,
+    // Episodes yet to air
+    pub airing: Vec<AiringEpisode>,
+    pub genres: Vec<String>,
+    pub tags: Vec<AnimeTag>,
+    pub studios: Vec<AnimeStudio>,
+    // Out of 100
+    pub average_score: Option<u32>,
+    // Users with the anime on their list
+    pub popularity: Option<u32>,
+    pub is_adult: bool,
+    pub relations: Vec<AnimeRelation>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AiringEpisode {
+    pub episode: u32,
+    pub airing_at: DateTime<Utc>,
+}
+
+// Most episodes worked out from a weekly broadcast, the same
+// number of upcoming episodes that is asked of anilist
+const MAX_BROADCAST_EPISODES: usize = 25;
+
+// Upcoming episodes of an anime that airs every week in the MAL broadcast slot,
+// counting from its first broadcast on or after `start_date`. MAL gives the slot
+// in Japan time, eg. "saturday" and "01:30"
+pub fn weekly_airing(
+    day_of_the_week: &str,
+    start_time: &str,
+    start_date: Option<NaiveDate>,
+    episodes: Option<u32>,
+    now: DateTime<Utc>,
+) -> Vec<AiringEpisode> {
+    let (Some(start_date), Ok(weekday), Ok(time)) = (
+        start_date,
+        day_of_the_week.parse::<Weekday>(),
+        NaiveTime::parse_from_str(start_time, "%H:%M"),
+    ) else {
+        return vec![];
+    };
+
+    let days_until =
+        (weekday.num_days_from_monday() + 7 - start_date.weekday().num_days_from_monday()) % 7;
+    let Some(first) = (start_date + Duration::days(days_until as i64))
+        .and_time(time)
+        .and_local_timezone(Tokyo)
+        .single()
+    else {
+        return vec![];
+    };
+    let first = first.with_timezone(&Utc);
+
+    // Skips the episodes that have aired without stepping through them
+    let aired = if now > first {
+        ((now - first).num_weeks() + 1) as u32
+    } else {
+        0
+    };
+
+    let last = episodes.unwrap_or(u32::MAX);
+    (aired + 1..=last)
+        .map(|episode| AiringEpisode {
+            episode,
+            airing_at: first + Duration::weeks(episode as i64 - 1),
+        })
+        .filter(|episode| episode.airing_at > now)
+        .take(MAX_BROADCAST_EPISODES)
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct AnimeTag {
+    pub name: String,
+    // Out of 100
+    pub rank: u32,
+    pub is_spoiler: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct AnimeStudio {
+    pub name: String,
+    // False for producers and studios that only helped
+    pub is_main: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct AnimeRelation {
+    pub id_mal: u32,
+    // One of the Relation values in the schema
+    pub relation_type: String,
+}
+
+#[derive(Debug, Default)]
+pub struct MetadataBatch {
+    pub animes: Vec<AnimeMetadata>,
+    // Ids the provider does not know about
+    pub not_found: Vec<u32>,
+}
+
+#[derive(Debug)]
+pub enum MetadataError {
+    RateLimited(DateTime<Utc>),
+    Request(anyhow::Error),
+    Parse(anyhow::Error),
+    // The batch was refused for its size, the ids in it are fine
+    TooLarge(anyhow::Error),
+}
+
+impl std::fmt::Display for MetadataError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MetadataError::RateLimited(until) => write!(f, "Rate limited until {}", until),
+            MetadataError::Request(err) => write!(f, "Request failed: {}", err),
+            MetadataError::Parse(err) => write!(f, "Failed to parse response: {}", err),
+            MetadataError::TooLarge(err) => write!(f, "Batch too large: {}", err),
+        }
+    }
+}
+
+#[async_trait]
+pub trait MetadataProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // Max number of ids that can be given to `fetch` at once
+    fn batch_size(&self) -> usize;
+
+    async fn fetch(&self, ids: Vec<u32>) -> Result<MetadataBatch, MetadataError>;
+}
+
+// Asks `fallback` for any animes `primary` could not find
+pub struct FallbackProvider<P, F> {
+    primary: P,
+    fallback: F,
+}
+
+impl<P, F> FallbackProvider<P, F> {
+    pub fn new(primary: P, fallback: F) -> Self {
+        FallbackProvider { primary, fallback }
+    }
+}
+
+#[async_trait]
+impl<P: MetadataProvider, F: MetadataProvider> MetadataProvider for FallbackProvider<P, F> {
+    fn name(&self) -> &'static str {
+        self.primary.name()
+    }
+
+    fn batch_size(&self) -> usize {
+        self.primary.batch_size()
+    }
+
+    async fn fetch(&self, ids: Vec<u32>) -> Result<MetadataBatch, MetadataError> {
+        let mut batch = self.primary.fetch(ids).await?;
+
+        if batch.not_found.is_empty() {
+            return Ok(batch);
+        }
+
+        let missing = std::mem::take(&mut batch.not_found);
+        for ids in missing.chunks(self.fallback.batch_size()) {
+            tracing::info!(
+                "Trying {} for {} animes missing from {}",
+                self.fallback.name(),
+                ids.len(),
+                self.primary.name()
+            );
+
+            match self.fallback.fetch(ids.to_vec()).await {
+                Ok(found) => {
+                    batch.animes.extend(found.animes);
+                    batch.not_found.extend(found.not_found);
+                }
+                Err(err) => {
+                    // Left out of the batch rather than reported missing,
+                    // so the importer backs them off and retries them
+                    tracing::error!("{} failed: {}", self.fallback.name(), err);
+                }
+            }
+        }
+
+        Ok(batch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weekly_airing_counts_from_first_broadcast() {
+        let now = "2026-10-18T00:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let date = |date: &str| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok();
+
+        // name, day, time, start date, episodes, expected (first episode, its airing time, count)
+        let cases = [
+            (
+                "airing, three episodes aired",
+                "saturday",
+                "01:30",
+                date("2026-10-03"),
+                Some(12),
+                Some((4, "2026-10-23T16:30:00+00:00", 9)),
+            ),
+            (
+                "start date before the broadcast day",
+                "saturday",
+                "01:30",
+                date("2026-10-01"),
+                Some(12),
+                Some((4, "2026-10-23T16:30:00+00:00", 9)),
+            ),
+            (
+                "not started yet",
+                "Sunday",
+                "23:00",
+                date("2026-11-01"),
+                Some(12),
+                Some((1, "2026-11-01T14:00:00+00:00", 12)),
+            ),
+            (
+                "unknown episode count",
+                "saturday",
+                "01:30",
+                date("2026-10-03"),
+                None,
+                Some((4, "2026-10-23T16:30:00+00:00", MAX_BROADCAST_EPISODES)),
+            ),
+            (
+                "every episode aired",
+                "saturday",
+                "01:30",
+                date("2026-10-03"),
+                Some(3),
+                None,
+            ),
+            ("no start date", "saturday", "01:30", None, Some(12), None),
+            (
+                "unknown day",
+                "someday",
+                "01:30",
+                date("2026-10-03"),
+                None,
+                None,
+            ),
+        ];
+
+        for (name, day, time, start_date, episodes, expected) in cases {
+            let airing = weekly_airing(day, time, start_date, episodes, now);
+
+            let got = airing
+                .first()
+                .map(|first| (first.episode, first.airing_at.to_rfc3339(), airing.len()));
+            let expected =
+                expected.map(|(episode, airing_at, count)| (episode, airing_at.to_string(), count));
+            assert_eq!(got, expected, "{}", name);
+        }
+    }
+}
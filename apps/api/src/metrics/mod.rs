@@ -0,0 +1,287 @@
+// Prometheus metrics, rendered in the text exposition format at /metrics
+// Everything is kept in memory and reset when the server restarts
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use axum::extract::{MatchedPath, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use lazy_static::lazy_static;
+
+use crate::importer::Lane;
+use crate::AppState;
+
+// Label name: value, sorted so the same labels always make the same series
+type Labels = Vec<(&'static str, String)>;
+
+// Upper bounds in seconds of the request duration buckets
+const DURATION_BUCKETS: [f64; 10] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Counter,
+    Histogram,
+}
+
+// name, type, help
+const METRICS: [(&str, Kind, &str); 7] = [
+    (
+        "importer_batches_total",
+        Kind::Counter,
+        "Metadata batches finished by the importer, by result",
+    ),
+    (
+        "upstream_requests_total",
+        Kind::Counter,
+        "Requests made to AniList, MAL and Jikan, by response status",
+    ),
+    (
+        "upstream_request_duration_seconds",
+        Kind::Histogram,
+        "Time taken by requests to AniList, MAL and Jikan",
+    ),
+    (
+        "rate_limit_sleeps_total",
+        Kind::Counter,
+        "Times requests were paused, to stay under a rate limit or after hitting one",
+    ),
+    (
+        "rate_limit_sleep_seconds_total",
+        Kind::Counter,
+        "Total time requests were paused for rate limits",
+    ),
+    (
+        "db_rows_inserted_total",
+        Kind::Counter,
+        "Rows inserted or updated by the importer, by table",
+    ),
+    (
+        "http_requests_total",
+        Kind::Counter,
+        "Requests handled by the api, by route and status",
+    ),
+];
+
+#[derive(Default)]
+struct Histogram {
+    // Same length as DURATION_BUCKETS, not cumulative
+    buckets: Vec<u64>,
+    count: u64,
+    sum: f64,
+}
+
+#[derive(Default)]
+struct Registry {
+    counters: BTreeMap<&'static str, BTreeMap<Labels, f64>>,
+    histograms: BTreeMap<&'static str, BTreeMap<Labels, Histogram>>,
+}
+
+lazy_static! {
+    static ref REGISTRY: Mutex<Registry> = Mutex::new(Registry::default());
+}
+
+fn labels(labels: &[(&'static str, &str)]) -> Labels {
+    let mut labels: Labels = labels
+        .iter()
+        .map(|(name, value)| (*name, value.to_string()))
+        .collect();
+    labels.sort();
+    labels
+}
+
+pub fn inc_counter_by(name: &'static str, label_values: &[(&'static str, &str)], by: f64) {
+    let mut registry = REGISTRY.lock().unwrap();
+    *registry
+        .counters
+        .entry(name)
+        .or_default()
+        .entry(labels(label_values))
+        .or_default() += by;
+}
+
+pub fn inc_counter(name: &'static str, label_values: &[(&'static str, &str)]) {
+    inc_counter_by(name, label_values, 1.0);
+}
+
+pub fn observe(name: &'static str, label_values: &[(&'static str, &str)], value: f64) {
+    let mut registry = REGISTRY.lock().unwrap();
+    let histogram = registry
+        .histograms
+        .entry(name)
+        .or_default()
+        .entry(labels(label_values))
+        .or_default();
+
+    if histogram.buckets.is_empty() {
+        histogram.buckets = vec![0; DURATION_BUCKETS.len()];
+    }
+    if let Some(i) = DURATION_BUCKETS.iter().position(|bound| value <= *bound) {
+        histogram.buckets[i] += 1;
+    }
+    histogram.count += 1;
+    histogram.sum += value;
+}
+
+// Records the status and duration of a request to `service`.
+// `status` is None when no response was received
+pub fn record_upstream(service: &str, status: Option<u16>, started: Instant) {
+    let status = status.map_or("error".to_string(), |status| status.to_string());
+    inc_counter(
+        "upstream_requests_total",
+        &[("service", service), ("status", &status)],
+    );
+    observe(
+        "upstream_request_duration_seconds",
+        &[("service", service)],
+        started.elapsed().as_secs_f64(),
+    );
+}
+
+fn format_labels(labels: &[(&'static str, String)], extra: Option<(&str, &str)>) -> String {
+    let mut parts: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+        .collect();
+    if let Some((name, value)) = extra {
+        parts.push(format!("{}=\"{}\"", name, value));
+    }
+
+    if parts.is_empty() {
+        return String::new();
+    }
+    format!("{{{}}}", parts.join(","))
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// `gauges` are values read at scrape time, as (name, help, labels, value)
+fn render(gauges: Vec<(&str, &str, Labels, f64)>) -> String {
+    let registry = REGISTRY.lock().unwrap();
+    let mut out = String::new();
+
+    let mut last_gauge = "";
+    for (name, help, labels, value) in &gauges {
+        if *name != last_gauge {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} gauge", name);
+            last_gauge = name;
+        }
+        let _ = writeln!(out, "{}{} {}", name, format_labels(labels, None), value);
+    }
+
+    for (name, kind, help) in METRICS {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        match kind {
+            Kind::Counter => {
+                let _ = writeln!(out, "# TYPE {} counter", name);
+                for (labels, value) in registry.counters.get(name).into_iter().flatten() {
+                    let _ = writeln!(out, "{}{} {}", name, format_labels(labels, None), value);
+                }
+            }
+            Kind::Histogram => {
+                let _ = writeln!(out, "# TYPE {} histogram", name);
+                for (labels, histogram) in registry.histograms.get(name).into_iter().flatten() {
+                    let mut cumulative = 0;
+                    for (bound, count) in DURATION_BUCKETS.iter().zip(histogram.buckets.iter()) {
+                        cumulative += count;
+                        let _ = writeln!(
+                            out,
+                            "{}_bucket{} {}",
+                            name,
+                            format_labels(labels, Some(("le", &bound.to_string()))),
+                            cumulative
+                        );
+                    }
+                    let _ = writeln!(
+                        out,
+                        "{}_bucket{} {}",
+                        name,
+                        format_labels(labels, Some(("le", "+Inf"))),
+                        histogram.count
+                    );
+                    let _ = writeln!(
+                        out,
+                        "{}_sum{} {}",
+                        name,
+                        format_labels(labels, None),
+                        histogram.sum
+                    );
+                    let _ = writeln!(
+                        out,
+                        "{}_count{} {}",
+                        name,
+                        format_labels(labels, None),
+                        histogram.count
+                    );
+                }
+            }
+        }
+    }
+
+    out
+}
+
+#[axum::debug_handler]
+pub async fn metrics_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let mut gauges = vec![];
+    if let Some(stats) = state.importer.stats().await {
+        for lane in Lane::ALL {
+            gauges.push((
+                "importer_queue_depth",
+                "Animes waiting to be imported, by lane",
+                labels(&[("lane", &format!("{:?}", lane).to_lowercase())]),
+                stats.lanes.get(&lane).copied().unwrap_or_default() as f64,
+            ));
+        }
+        gauges.push((
+            "importer_retrying",
+            "Queued animes waiting to be retried after a failure",
+            vec![],
+            stats.retrying as f64,
+        ));
+        gauges.push((
+            "importer_ignored",
+            "Animes on the ignore list",
+            vec![],
+            stats.ignored_ids.len() as f64,
+        ));
+    }
+
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        render(gauges),
+    )
+}
+
+// Counts requests by the route they matched, not the raw path,
+// so ids in the path do not create a series each
+pub async fn track_requests(request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched".to_string(), |path| path.as_str().to_string());
+
+    let response = next.run(request).await;
+
+    inc_counter(
+        "http_requests_total",
+        &[
+            ("method", &method),
+            ("route", &route),
+            ("status", response.status().as_str()),
+        ],
+    );
+
+    response
+}
@@ -0,0 +1,21 @@
+use crate::models::user::DBUser;
+use crate::AppState;
+
+use axum::extract::{Request, State};
+use axum::Extension;
+
+use axum::{http::StatusCode, middleware::Next, response::Response};
+
+// Must be layered inside `guard` so the user is available
+pub async fn admin_guard(
+    State(state): State<AppState>,
+    Extension(user): Extension<DBUser>,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    if !state.admin_mal_ids.contains(&user.mal_id) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    Ok(next.run(request).await)
+}
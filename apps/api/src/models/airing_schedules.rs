@@ -0,0 +1,190 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::prelude::FromRow;
+use sqlx::{MySql, Pool, QueryBuilder};
+
+use crate::consts::MYSQL_PARAM_BIND_LIMIT;
+use crate::metrics;
+
+#[derive(Debug, Clone)]
+pub struct InsertAiringEpisode {
+    pub anime_id: u32,
+    pub episode: u32,
+    pub airing_at: DateTime<Utc>,
+}
+
+// An upcoming episode of an anime on a users list
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct DBScheduledEpisode {
+    pub anime_id: i32,
+    pub episode: i32,
+    pub airing_at: DateTime<Utc>,
+    pub romaji_title: String,
+    pub english_title: Option<String>,
+    pub picture: String,
+    // Minutes per episode
+    pub duration: Option<i32>,
+    pub anime_status: String,
+    pub watch_status: String,
+}
+
+// Replaces the episodes yet to air of every anime in `ids` with `episodes`.
+// Episodes that have already aired are kept
+pub async fn replace_airing_schedules(
+    db: &Pool<MySql>,
+    ids: &[u32],
+    episodes: Vec<InsertAiringEpisode>,
+) -> Result<(), anyhow::Error> {
+    if ids.is_empty() {
+        return Ok(());
+    }
+
+    let now = Utc::now();
+    let mut tx = db.begin().await?;
+
+    for group in ids.chunks(MYSQL_PARAM_BIND_LIMIT - 1) {
+        let mut query_builder: QueryBuilder<MySql> =
+            QueryBuilder::new("DELETE FROM airing_schedules WHERE airing_at > ");
+        query_builder.push_bind(now).push(" AND anime_id IN (");
+
+        let mut separated = query_builder.separated(", ");
+        for id in group {
+            separated.push_bind(id);
+        }
+        separated.push_unseparated(")");
+
+        query_builder.build().execute(&mut *tx).await?;
+    }
+
+    for group in episodes.chunks(MYSQL_PARAM_BIND_LIMIT / 3) {
+        let mut query_builder: QueryBuilder<MySql> =
+            QueryBuilder::new("INSERT INTO airing_schedules (anime_id, episode, airing_at) ");
+        query_builder.push_values(group.iter(), |mut b, episode| {
+            b.push_bind(episode.anime_id)
+                .push_bind(episode.episode)
+                .push_bind(episode.airing_at);
+        });
+        // An episode that was delayed until after it was meant to air
+        query_builder.push(" ON DUPLICATE KEY UPDATE airing_at = VALUES(airing_at)");
+
+        query_builder.build().execute(&mut *tx).await?;
+    }
+
+    tx.commit().await?;
+
+    tracing::info!("Inserted {} airing episodes", episodes.len());
+    metrics::inc_counter_by(
+        "db_rows_inserted_total",
+        &[("table", "airing_schedules")],
+        episodes.len() as f64,
+    );
+
+    Ok(())
+}
+
+// Episodes airing between `from` and `to` of animes the user
+// is watching or plans to watch, in the order they air
+pub async fn get_user_schedule(
+    db: &Pool<MySql>,
+    user_id: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<DBScheduledEpisode>, anyhow::Error> {
+    let mut query_builder: QueryBuilder<MySql> = QueryBuilder::new(
+        r#"
+        SELECT
+            airing_schedules.anime_id,
+            airing_schedules.episode,
+            airing_schedules.airing_at,
+            animes.romaji_title,
+            animes.english_title,
+            animes.picture,
+            animes.duration,
+            CAST(animes.status AS CHAR) AS anime_status,
+            CAST(anime_users.status AS CHAR) AS watch_status
+        FROM airing_schedules
+        JOIN anime_users ON anime_users.anime_id = airing_schedules.anime_id
+        JOIN animes ON animes.id = airing_schedules.anime_id
+        WHERE anime_users.status IN ("WATCHING", "PLAN_TO_WATCH") AND anime_users.user_id = "#,
+    );
+    query_builder
+        .push_bind(user_id)
+        .push(" AND airing_schedules.airing_at >= ")
+        .push_bind(from)
+        .push(" AND airing_schedules.airing_at < ")
+        .push_bind(to)
+        .push(" ORDER BY airing_schedules.airing_at, airing_schedules.anime_id");
+
+    let episodes = query_builder
+        .build_query_as::<DBScheduledEpisode>()
+        .fetch_all(db)
+        .await?;
+
+    Ok(episodes)
+}
+
+// Weekly broadcast slot of an anime, as MAL gives it in Japan time
+#[derive(Debug, Clone, FromRow)]
+pub struct DBAnimeBroadcast {
+    pub anime_id: i32,
+    // eg. saturday
+    pub day_of_the_week: String,
+    // eg. 01:30
+    pub start_time: String,
+}
+
+pub async fn upsert_anime_broadcasts(
+    db: &Pool<MySql>,
+    broadcasts: Vec<DBAnimeBroadcast>,
+) -> Result<(), anyhow::Error> {
+    if broadcasts.is_empty() {
+        return Ok(());
+    }
+
+    for group in broadcasts.chunks(MYSQL_PARAM_BIND_LIMIT / 3) {
+        let mut query_builder: QueryBuilder<MySql> = QueryBuilder::new(
+            "INSERT INTO anime_broadcasts (anime_id, day_of_the_week, start_time) ",
+        );
+        query_builder.push_values(group.iter(), |mut b, broadcast| {
+            b.push_bind(broadcast.anime_id)
+                .push_bind(broadcast.day_of_the_week.clone())
+                .push_bind(broadcast.start_time.clone());
+        });
+        query_builder.push(
+            " ON DUPLICATE KEY UPDATE day_of_the_week = VALUES(day_of_the_week), \
+            start_time = VALUES(start_time), updated_at = NOW()",
+        );
+
+        query_builder.build().execute(db).await?;
+    }
+
+    Ok(())
+}
+
+pub async fn get_anime_broadcasts(
+    db: &Pool<MySql>,
+    ids: &[u32],
+) -> Result<Vec<DBAnimeBroadcast>, anyhow::Error> {
+    let mut broadcasts = vec![];
+
+    for group in ids.chunks(MYSQL_PARAM_BIND_LIMIT) {
+        let mut query_builder: QueryBuilder<MySql> = QueryBuilder::new(
+            "SELECT anime_id, day_of_the_week, start_time FROM anime_broadcasts WHERE anime_id IN (",
+        );
+
+        let mut separated = query_builder.separated(", ");
+        for id in group {
+            separated.push_bind(id);
+        }
+        separated.push_unseparated(")");
+
+        broadcasts.extend(
+            query_builder
+                .build_query_as::<DBAnimeBroadcast>()
+                .fetch_all(db)
+                .await?,
+        );
+    }
+
+    Ok(broadcasts)
+}
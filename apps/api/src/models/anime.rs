@@ -1,18 +1,32 @@
-use chrono::NaiveDateTime;
+use chrono::{NaiveDate, NaiveDateTime};
 use serde::Serialize;
 use sqlx::prelude::FromRow;
 use sqlx::{MySql, Pool, QueryBuilder};
 
-use crate::anilist::api_types::{CoverImage, Title};
+use crate::consts::MYSQL_PARAM_BIND_LIMIT;
+use crate::metrics;
+use crate::models::anime_details::{get_anime_details, AnimeDetails};
 
 #[derive(Debug, Clone)]
 pub struct InsertAnime {
     pub status: String,
-    pub title: Title,
+    pub format: Option<String>,
+    pub romaji_title: Option<String>,
+    pub english_title: Option<String>,
+    pub native_title: Option<String>,
     pub id_mal: u32,
-    pub cover_image: CoverImage,
+    pub picture: String,
+    pub banner_image: Option<String>,
+    pub description: Option<String>,
     pub season: Option<String>,
     pub season_year: Option<u32>,
+    pub start_date: Option<NaiveDate>,
+    pub end_date: Option<NaiveDate>,
+    pub episodes: Option<u32>,
+    pub duration: Option<u32>,
+    pub average_score: Option<u32>,
+    pub popularity: Option<u32>,
+    pub is_adult: bool,
 }
 
 pub async fn insert_animes(db: &Pool<MySql>, animes: Vec<InsertAnime>) -> Result<(), sqlx::Error> {
@@ -21,27 +35,60 @@ pub async fn insert_animes(db: &Pool<MySql>, animes: Vec<InsertAnime>) -> Result
     }
     let mut query_builder = QueryBuilder::new(
         r#"
-        INSERT INTO animes (id, romaji_title,  status, picture, season, season_year, updated_at)
+        INSERT INTO animes (
+            id, romaji_title, english_title, native_title, status, format, picture, banner_image,
+            description, season, season_year, start_date, end_date, episodes, duration,
+            average_score, popularity, is_adult, updated_at
+        )
         "#,
     );
 
     query_builder.push_values(animes.iter(), |mut b, anime| {
         b.push_bind(anime.id_mal)
-            .push_bind(anime.title.romaji.clone())
+            .push_bind(anime.romaji_title.clone())
+            .push_bind(anime.english_title.clone())
+            .push_bind(anime.native_title.clone())
             .push_bind(anime.status.clone())
-            .push_bind(anime.cover_image.large.clone())
+            .push_bind(anime.format.clone())
+            .push_bind(anime.picture.clone())
+            .push_bind(anime.banner_image.clone())
+            .push_bind(anime.description.clone())
             .push_bind(anime.season.clone())
             .push_bind(anime.season_year)
+            .push_bind(anime.start_date)
+            .push_bind(anime.end_date)
+            .push_bind(anime.episodes)
+            .push_bind(anime.duration)
+            .push_bind(anime.average_score)
+            .push_bind(anime.popularity)
+            .push_bind(anime.is_adult)
             .push_bind(chrono::Utc::now());
     });
 
-    query_builder.push("ON DUPLICATE KEY UPDATE romaji_title = VALUES(romaji_title), status = VALUES(status), picture = VALUES(picture), season = VALUES(season), season_year = VALUES(season_year), updated_at = VALUES(updated_at)");
+    query_builder.push(
+        r#"
+        ON DUPLICATE KEY UPDATE
+            romaji_title = VALUES(romaji_title), english_title = VALUES(english_title),
+            native_title = VALUES(native_title), status = VALUES(status), format = VALUES(format),
+            picture = VALUES(picture), banner_image = VALUES(banner_image),
+            description = VALUES(description), season = VALUES(season),
+            season_year = VALUES(season_year), start_date = VALUES(start_date),
+            end_date = VALUES(end_date), episodes = VALUES(episodes), duration = VALUES(duration),
+            average_score = VALUES(average_score), popularity = VALUES(popularity),
+            is_adult = VALUES(is_adult), updated_at = VALUES(updated_at)
+        "#,
+    );
 
     let q = query_builder.build();
 
-    q.execute(db).await.expect("Failed to insert anime");
+    q.execute(db).await?;
 
     tracing::info!("Inserted {} animes", animes.len());
+    metrics::inc_counter_by(
+        "db_rows_inserted_total",
+        &[("table", "animes")],
+        animes.len() as f64,
+    );
 
     Ok(())
 }
@@ -51,12 +98,27 @@ pub struct DBAnime {
     pub id: i32,
     pub english_title: Option<String>,
     pub romaji_title: String,
+    pub native_title: Option<String>,
     pub status: String,
+    pub format: Option<String>,
     pub picture: String,
+    pub banner_image: Option<String>,
+    pub description: Option<String>,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
     pub season: Option<String>,
     pub season_year: Option<i32>,
+    pub start_date: Option<NaiveDate>,
+    pub end_date: Option<NaiveDate>,
+    pub episodes: Option<i32>,
+    pub duration: Option<i32>,
+    pub average_score: Option<i32>,
+    pub popularity: Option<i32>,
+    pub is_adult: bool,
+    // Filled from the other anime tables by `get_anime_details`
+    #[sqlx(skip)]
+    #[serde(flatten)]
+    pub details: AnimeDetails,
 }
 
 pub async fn get_released_animes_by_id(
@@ -89,7 +151,106 @@ pub async fn get_released_animes_by_id(
 
     let query = query_builder.build_query_as::<DBAnime>();
 
-    let animes = query.fetch_all(db).await?;
+    let mut animes = query.fetch_all(db).await?;
+
+    let ids: Vec<i32> = animes.iter().map(|anime| anime.id).collect();
+    let mut details = get_anime_details(db, &ids).await?;
+    for anime in animes.iter_mut() {
+        anime.details = details.remove(&anime.id).unwrap_or_default();
+    }
 
     Ok(animes)
 }
+
+// The subset of `ids` that have been imported
+pub async fn get_existing_anime_ids(
+    db: &Pool<MySql>,
+    ids: &[u32],
+) -> Result<Vec<i32>, anyhow::Error> {
+    let mut existing = vec![];
+
+    for group in ids.chunks(MYSQL_PARAM_BIND_LIMIT) {
+        let mut query_builder: QueryBuilder<MySql> =
+            QueryBuilder::new("SELECT id FROM animes WHERE id IN (");
+
+        let mut separated = query_builder.separated(", ");
+        for id in group {
+            separated.push_bind(id);
+        }
+        separated.push_unseparated(")");
+
+        let rows = query_builder
+            .build_query_as::<(i32,)>()
+            .fetch_all(db)
+            .await?;
+        existing.extend(rows.into_iter().map(|row| row.0));
+    }
+
+    Ok(existing)
+}
+
+// Animes in `ids` that are stored and have been updated since the given cutoffs,
+// the opposite of `get_stale_anime_ids`
+pub async fn get_fresh_anime_ids(
+    db: &Pool<MySql>,
+    ids: &[u32],
+    airing_before: NaiveDateTime,
+    finished_before: NaiveDateTime,
+) -> Result<Vec<i32>, anyhow::Error> {
+    let mut fresh = vec![];
+
+    for group in ids.chunks(MYSQL_PARAM_BIND_LIMIT - 2) {
+        let mut query_builder: QueryBuilder<MySql> = QueryBuilder::new(
+            r#"
+            SELECT id FROM animes
+            WHERE IF(status IN ("RELEASING", "NOT_YET_RELEASED"), updated_at >= "#,
+        );
+        query_builder
+            .push_bind(airing_before)
+            .push(", updated_at >= ")
+            .push_bind(finished_before)
+            .push(") AND id IN (");
+
+        let mut separated = query_builder.separated(", ");
+        for id in group {
+            separated.push_bind(id);
+        }
+        separated.push_unseparated(")");
+
+        let rows = query_builder
+            .build_query_as::<(i32,)>()
+            .fetch_all(db)
+            .await?;
+        fresh.extend(rows.into_iter().map(|row| row.0));
+    }
+
+    Ok(fresh)
+}
+
+// Animes that have not been updated since the given cutoffs, oldest first
+// Animes still airing (or yet to air) use `airing_before`, everything else `finished_before`
+pub async fn get_stale_anime_ids(
+    db: &Pool<MySql>,
+    airing_before: NaiveDateTime,
+    finished_before: NaiveDateTime,
+    limit: u32,
+) -> Result<Vec<i32>, anyhow::Error> {
+    let mut query_builder: QueryBuilder<MySql> = QueryBuilder::new(
+        r#"
+        SELECT id FROM animes
+        WHERE (status IN ("RELEASING", "NOT_YET_RELEASED") AND updated_at < "#,
+    );
+    query_builder
+        .push_bind(airing_before)
+        .push(r#") OR (status NOT IN ("RELEASING", "NOT_YET_RELEASED") AND updated_at < "#)
+        .push_bind(finished_before)
+        .push(") ORDER BY updated_at LIMIT ")
+        .push_bind(limit);
+
+    let rows = query_builder
+        .build_query_as::<(i32,)>()
+        .fetch_all(db)
+        .await?;
+
+    Ok(rows.into_iter().map(|row| row.0).collect())
+}
@@ -0,0 +1,232 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::Serialize;
+use sqlx::prelude::FromRow;
+use sqlx::{MySql, Pool, QueryBuilder};
+
+use crate::consts::MYSQL_PARAM_BIND_LIMIT;
+use crate::metadata::{AnimeStudio, AnimeTag};
+use crate::metrics;
+
+// Length of prisma's default String column, longer synonyms would fail the insert
+const MAX_SYNONYM_LENGTH: usize = 191;
+
+// Everything about an anime that is stored outside the animes table
+#[derive(Debug, Clone)]
+pub struct InsertAnimeDetails {
+    pub anime_id: u32,
+    pub synonyms: Vec<String>,
+    pub genres: Vec<String>,
+    pub tags: Vec<AnimeTag>,
+    pub studios: Vec<AnimeStudio>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AnimeDetails {
+    pub synonyms: Vec<String>,
+    pub genres: Vec<String>,
+    pub tags: Vec<DBAnimeTag>,
+    pub studios: Vec<DBAnimeStudio>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct DBAnimeTag {
+    #[serde(skip)]
+    pub anime_id: i32,
+    pub tag: String,
+    pub rank: i32,
+    pub is_spoiler: bool,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct DBAnimeStudio {
+    #[serde(skip)]
+    pub anime_id: i32,
+    pub studio: String,
+    pub is_main: bool,
+}
+
+// Replaces the synonyms, genres, tags and studios of every anime in `animes`
+pub async fn replace_anime_details(
+    db: &Pool<MySql>,
+    animes: Vec<InsertAnimeDetails>,
+) -> Result<(), anyhow::Error> {
+    if animes.is_empty() {
+        return Ok(());
+    }
+
+    let ids: Vec<u32> = animes.iter().map(|anime| anime.anime_id).collect();
+
+    let mut synonyms = vec![];
+    let mut seen_synonyms = HashSet::new();
+    let mut genres = vec![];
+    let mut tags = vec![];
+    let mut studios = vec![];
+    for anime in &animes {
+        for synonym in &anime.synonyms {
+            // Anilist sometimes lists the same synonym twice, and the
+            // primary key does not tell "Shingeki" and "shingeki" apart
+            if synonym.chars().count() <= MAX_SYNONYM_LENGTH
+                && seen_synonyms.insert((anime.anime_id, synonym.to_lowercase()))
+            {
+                synonyms.push((anime.anime_id, synonym));
+            }
+        }
+        genres.extend(anime.genres.iter().map(|genre| (anime.anime_id, genre)));
+        tags.extend(anime.tags.iter().map(|tag| (anime.anime_id, tag)));
+        studios.extend(anime.studios.iter().map(|studio| (anime.anime_id, studio)));
+    }
+
+    let mut tx = db.begin().await?;
+
+    for table in [
+        "anime_synonyms",
+        "anime_genres",
+        "anime_tags",
+        "anime_studios",
+    ] {
+        for group in ids.chunks(MYSQL_PARAM_BIND_LIMIT) {
+            let mut query_builder: QueryBuilder<MySql> =
+                QueryBuilder::new(format!("DELETE FROM {} WHERE anime_id IN (", table));
+
+            let mut separated = query_builder.separated(", ");
+            for id in group {
+                separated.push_bind(id);
+            }
+            separated.push_unseparated(")");
+
+            query_builder.build().execute(&mut *tx).await?;
+        }
+    }
+
+    for group in synonyms.chunks(MYSQL_PARAM_BIND_LIMIT / 2) {
+        let mut query_builder: QueryBuilder<MySql> =
+            QueryBuilder::new("INSERT IGNORE INTO anime_synonyms (anime_id, synonym) ");
+        query_builder.push_values(group.iter(), |mut b, (anime_id, synonym)| {
+            b.push_bind(anime_id).push_bind(synonym.as_str());
+        });
+        query_builder.build().execute(&mut *tx).await?;
+    }
+
+    for group in genres.chunks(MYSQL_PARAM_BIND_LIMIT / 2) {
+        let mut query_builder: QueryBuilder<MySql> =
+            QueryBuilder::new("INSERT IGNORE INTO anime_genres (anime_id, genre) ");
+        query_builder.push_values(group.iter(), |mut b, (anime_id, genre)| {
+            b.push_bind(anime_id).push_bind(genre.as_str());
+        });
+        query_builder.build().execute(&mut *tx).await?;
+    }
+
+    for group in tags.chunks(MYSQL_PARAM_BIND_LIMIT / 4) {
+        let mut query_builder: QueryBuilder<MySql> =
+            QueryBuilder::new("INSERT IGNORE INTO anime_tags (anime_id, tag, `rank`, is_spoiler) ");
+        query_builder.push_values(group.iter(), |mut b, (anime_id, tag)| {
+            b.push_bind(anime_id)
+                .push_bind(tag.name.as_str())
+                .push_bind(tag.rank)
+                .push_bind(tag.is_spoiler);
+        });
+        query_builder.build().execute(&mut *tx).await?;
+    }
+
+    for group in studios.chunks(MYSQL_PARAM_BIND_LIMIT / 3) {
+        let mut query_builder: QueryBuilder<MySql> =
+            QueryBuilder::new("INSERT IGNORE INTO anime_studios (anime_id, studio, is_main) ");
+        query_builder.push_values(group.iter(), |mut b, (anime_id, studio)| {
+            b.push_bind(anime_id)
+                .push_bind(studio.name.as_str())
+                .push_bind(studio.is_main);
+        });
+        query_builder.build().execute(&mut *tx).await?;
+    }
+
+    tx.commit().await?;
+
+    tracing::info!("Replaced details of {} animes", animes.len());
+    for (table, rows) in [
+        ("anime_synonyms", synonyms.len()),
+        ("anime_genres", genres.len()),
+        ("anime_tags", tags.len()),
+        ("anime_studios", studios.len()),
+    ] {
+        metrics::inc_counter_by("db_rows_inserted_total", &[("table", table)], rows as f64);
+    }
+
+    Ok(())
+}
+
+// Synonyms, genres, tags and studios of `ids`, keyed by anime id.
+// Animes without any are left out
+pub async fn get_anime_details(
+    db: &Pool<MySql>,
+    ids: &[i32],
+) -> Result<HashMap<i32, AnimeDetails>, anyhow::Error> {
+    let mut details: HashMap<i32, AnimeDetails> = HashMap::new();
+
+    for group in ids.chunks(MYSQL_PARAM_BIND_LIMIT) {
+        let synonyms = select_in(
+            "SELECT anime_id, synonym FROM anime_synonyms WHERE anime_id IN (",
+            group,
+        )
+        .build_query_as::<(i32, String)>()
+        .fetch_all(db)
+        .await?;
+        for (anime_id, synonym) in synonyms {
+            details.entry(anime_id).or_default().synonyms.push(synonym);
+        }
+
+        let genres = select_in(
+            "SELECT anime_id, genre FROM anime_genres WHERE anime_id IN (",
+            group,
+        )
+        .build_query_as::<(i32, String)>()
+        .fetch_all(db)
+        .await?;
+        for (anime_id, genre) in genres {
+            details.entry(anime_id).or_default().genres.push(genre);
+        }
+
+        let tags = select_in(
+            "SELECT anime_id, tag, `rank`, is_spoiler FROM anime_tags WHERE anime_id IN (",
+            group,
+        )
+        .push(" ORDER BY `rank` DESC")
+        .build_query_as::<DBAnimeTag>()
+        .fetch_all(db)
+        .await?;
+        for tag in tags {
+            details.entry(tag.anime_id).or_default().tags.push(tag);
+        }
+
+        let studios = select_in(
+            "SELECT anime_id, studio, is_main FROM anime_studios WHERE anime_id IN (",
+            group,
+        )
+        .push(" ORDER BY is_main DESC")
+        .build_query_as::<DBAnimeStudio>()
+        .fetch_all(db)
+        .await?;
+        for studio in studios {
+            details
+                .entry(studio.anime_id)
+                .or_default()
+                .studios
+                .push(studio);
+        }
+    }
+
+    Ok(details)
+}
+
+// `select` followed by the bound `ids` and a closing bracket
+fn select_in<'a>(select: &str, ids: &'a [i32]) -> QueryBuilder<'a, MySql> {
+    let mut query_builder: QueryBuilder<MySql> = QueryBuilder::new(select);
+
+    let mut separated = query_builder.separated(", ");
+    for id in ids {
+        separated.push_bind(id);
+    }
+    separated.push_unseparated(")");
+
+    query_builder
+}
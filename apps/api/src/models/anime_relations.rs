@@ -1,5 +1,8 @@
 use sqlx::{MySql, Pool, QueryBuilder};
 
+use crate::consts::MYSQL_PARAM_BIND_LIMIT;
+use crate::metrics;
+
 pub async fn create_anime_relation(
     db: &Pool<MySql>,
     items: Vec<(u32, u32, String)>,
@@ -23,9 +26,51 @@ pub async fn create_anime_relation(
 
     let q = query_builder.build();
 
-    q.execute(db).await.expect("Failed to insert anime");
+    q.execute(db).await?;
 
     tracing::info!("Inserted {} anime relations", items.len());
+    metrics::inc_counter_by(
+        "db_rows_inserted_total",
+        &[("table", "anime_relations")],
+        items.len() as f64,
+    );
 
     Ok(())
 }
+
+// PREQUEL and SEQUEL relations going to or from any of `ids`
+pub async fn get_sequel_relations(
+    db: &Pool<MySql>,
+    ids: &[u32],
+) -> Result<Vec<(i32, i32, String)>, anyhow::Error> {
+    let mut relations = vec![];
+
+    for group in ids.chunks(MYSQL_PARAM_BIND_LIMIT / 2) {
+        let mut query_builder: QueryBuilder<MySql> = QueryBuilder::new(
+            r#"
+            SELECT anime_id, relation_id, CAST(relation AS CHAR) FROM anime_relations
+            WHERE relation IN ("PREQUEL", "SEQUEL") AND (anime_id IN (
+            "#,
+        );
+
+        let mut separated = query_builder.separated(", ");
+        for id in group {
+            separated.push_bind(id);
+        }
+        separated.push_unseparated(") OR relation_id IN (");
+
+        let mut separated = query_builder.separated(", ");
+        for id in group {
+            separated.push_bind(id);
+        }
+        separated.push_unseparated("))");
+
+        let rows = query_builder
+            .build_query_as::<(i32, i32, String)>()
+            .fetch_all(db)
+            .await?;
+        relations.extend(rows);
+    }
+
+    Ok(relations)
+}
@@ -0,0 +1,58 @@
+use sqlx::{MySql, Pool, QueryBuilder};
+
+use crate::consts::MYSQL_PARAM_BIND_LIMIT;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InsertAnimeSeries {
+    pub series_id: u32,
+    pub anime_id: u32,
+    pub series_order: u32,
+}
+
+// Replaces whatever series `anime_ids` were in with `series`
+pub async fn replace_anime_series(
+    db: &Pool<MySql>,
+    anime_ids: &[u32],
+    series: Vec<InsertAnimeSeries>,
+) -> Result<(), anyhow::Error> {
+    if anime_ids.is_empty() {
+        return Ok(());
+    }
+
+    let mut tx = db.begin().await?;
+
+    for group in anime_ids.chunks(MYSQL_PARAM_BIND_LIMIT) {
+        let mut query_builder: QueryBuilder<MySql> =
+            QueryBuilder::new("DELETE FROM anime_series WHERE anime_id IN (");
+
+        let mut separated = query_builder.separated(", ");
+        for id in group {
+            separated.push_bind(id);
+        }
+        separated.push_unseparated(")");
+
+        query_builder.build().execute(&mut *tx).await?;
+    }
+
+    for group in series.chunks(MYSQL_PARAM_BIND_LIMIT / 3) {
+        let mut query_builder: QueryBuilder<MySql> = QueryBuilder::new(
+            r#"
+            INSERT INTO anime_series (series_id, anime_id, series_order)
+            "#,
+        );
+
+        query_builder.push_values(group.iter(), |mut b, entry| {
+            b.push_bind(entry.series_id)
+                .push_bind(entry.anime_id)
+                .push_bind(entry.series_order);
+        });
+
+        query_builder.build().execute(&mut *tx).await?;
+    }
+
+    tx.commit().await?;
+
+    tracing::info!("Updated series for {} animes", series.len());
+
+    Ok(())
+}
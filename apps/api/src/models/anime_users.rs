@@ -1,19 +1,25 @@
 use std::sync::Arc;
 
-use chrono::NaiveDateTime;
+use chrono::{NaiveDate, NaiveDateTime};
 use serde::Deserialize;
 use sqlx::{MySql, Pool, QueryBuilder};
 
 use crate::consts::MYSQL_PARAM_BIND_LIMIT;
 use crate::importer::{AnimeUserEntry, AnimeWatchStatus};
+use crate::mal::MalListStatusUpdate;
 
 pub struct DBAnimeUser {
-    pub user_id: String,
     pub anime_id: i32,
     pub status: AnimeWatchStatus,
     pub watch_priority: i32,
-    pub created_at: NaiveDateTime,
-    pub updated_at: NaiveDateTime,
+    pub score: i32,
+    pub num_episodes_watched: i32,
+    pub is_rewatching: bool,
+    pub start_date: Option<NaiveDate>,
+    pub finish_date: Option<NaiveDate>,
+    pub mal_priority: i32,
+    pub comments: Option<String>,
+    pub mal_updated_at: Option<NaiveDateTime>,
 }
 
 pub async fn link_user_to_anime(
@@ -24,12 +30,6 @@ pub async fn link_user_to_anime(
         return Ok(());
     }
 
-    let mut query_builder = QueryBuilder::new(
-        r#"
-        INSERT INTO anime_users (user_id, anime_id, status, watch_priority)
-        "#,
-    );
-
     let flat_entries: Vec<AnimeUserEntry> =
         items.into_iter().flat_map(|(_, strings)| strings).collect();
 
@@ -37,20 +37,45 @@ pub async fn link_user_to_anime(
         return Ok(());
     }
 
-    query_builder.push_values(flat_entries, |mut b, item| {
-        let status_str: String = item.status.into();
-        b.push_bind(item.user_id)
-            .push_bind(item.anime_id)
-            .push_bind(status_str)
-            .push_bind(0);
-    });
-
-    query_builder
-        .push("ON DUPLICATE KEY UPDATE status = VALUES(status), updated_at = VALUES(updated_at)");
-
-    let q = query_builder.build();
+    for group in flat_entries.chunks(MYSQL_PARAM_BIND_LIMIT / 12) {
+        let mut query_builder = QueryBuilder::new(
+            r#"
+            INSERT INTO anime_users (user_id, anime_id, status, watch_priority, score,
+            num_episodes_watched, is_rewatching, start_date, finish_date, mal_priority,
+            comments, mal_updated_at)
+            "#,
+        );
+
+        query_builder.push_values(group, |mut b, item| {
+            let status_str: String = item.status.clone().into();
+            let list_status = &item.list_status;
+            b.push_bind(item.user_id.clone())
+                .push_bind(item.anime_id)
+                .push_bind(status_str)
+                .push_bind(0)
+                .push_bind(list_status.score)
+                .push_bind(list_status.num_episodes_watched)
+                .push_bind(list_status.is_rewatching)
+                .push_bind(list_status.start_date)
+                .push_bind(list_status.finish_date)
+                .push_bind(list_status.priority)
+                .push_bind(list_status.comments.clone())
+                .push_bind(list_status.updated_at);
+        });
 
-    q.execute(db).await.expect("Failed to insert anime_user");
+        query_builder.push(
+            r#"
+            ON DUPLICATE KEY UPDATE status = VALUES(status), score = VALUES(score),
+            num_episodes_watched = VALUES(num_episodes_watched),
+            is_rewatching = VALUES(is_rewatching), start_date = VALUES(start_date),
+            finish_date = VALUES(finish_date), mal_priority = VALUES(mal_priority),
+            comments = VALUES(comments), mal_updated_at = VALUES(mal_updated_at),
+            updated_at = VALUES(updated_at)
+            "#,
+        );
+
+        query_builder.build().execute(db).await?;
+    }
 
     Ok(())
 }
@@ -96,7 +121,70 @@ pub async fn get_user_entrys(
 ) -> Result<Vec<DBAnimeUser>, anyhow::Error> {
     let rows = sqlx::query_as!(
         DBAnimeUser,
-        "SELECT * from anime_users WHERE user_id = ? AND status in (\"plan_to_watch\", \"watching\")",
+        "SELECT anime_id, status, watch_priority, score, num_episodes_watched, is_rewatching,
+        start_date, finish_date, mal_priority, comments, mal_updated_at
+        FROM anime_users WHERE user_id = ? AND status in (\"plan_to_watch\", \"watching\")",
+        user_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(rows)
+}
+
+pub async fn get_user_entry(
+    db: &Pool<MySql>,
+    user_id: &str,
+    anime_id: u32,
+) -> Result<Option<DBAnimeUser>, anyhow::Error> {
+    let row = sqlx::query_as!(
+        DBAnimeUser,
+        "SELECT anime_id, status, watch_priority, score, num_episodes_watched, is_rewatching,
+        start_date, finish_date, mal_priority, comments, mal_updated_at
+        FROM anime_users WHERE user_id = ? AND anime_id = ?",
+        user_id,
+        anime_id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(row)
+}
+
+// Changes the fields set in `update`, the rest are left as they are
+pub async fn update_user_entry(
+    db: &Pool<MySql>,
+    user_id: &str,
+    anime_id: u32,
+    update: &MalListStatusUpdate,
+) -> Result<(), anyhow::Error> {
+    let status: Option<String> = update.status.clone().map(String::from);
+    sqlx::query!(
+        "UPDATE anime_users SET status = COALESCE(?, status), score = COALESCE(?, score),
+        num_episodes_watched = COALESCE(?, num_episodes_watched), updated_at = NOW()
+        WHERE user_id = ? AND anime_id = ?",
+        status,
+        update.score,
+        update.num_episodes_watched,
+        user_id,
+        anime_id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+// Every entry on the users list, whatever its status
+pub async fn get_all_user_entries(
+    db: &Pool<MySql>,
+    user_id: &str,
+) -> Result<Vec<DBAnimeUser>, anyhow::Error> {
+    let rows = sqlx::query_as!(
+        DBAnimeUser,
+        "SELECT anime_id, status, watch_priority, score, num_episodes_watched, is_rewatching,
+        start_date, finish_date, mal_priority, comments, mal_updated_at
+        FROM anime_users WHERE user_id = ?",
         user_id
     )
     .fetch_all(db)
@@ -104,3 +192,25 @@ pub async fn get_user_entrys(
 
     Ok(rows)
 }
+
+pub async fn remove_user_entries(
+    db: &Pool<MySql>,
+    user_id: &str,
+    anime_ids: &[u32],
+) -> Result<(), anyhow::Error> {
+    for group in anime_ids.chunks(MYSQL_PARAM_BIND_LIMIT - 1) {
+        let mut query_builder: QueryBuilder<MySql> =
+            QueryBuilder::new("DELETE FROM anime_users WHERE user_id = ");
+        query_builder.push_bind(user_id).push(" AND anime_id IN (");
+
+        let mut separated = query_builder.separated(", ");
+        for id in group {
+            separated.push_bind(id);
+        }
+        separated.push_unseparated(")");
+
+        query_builder.build().execute(db).await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,68 @@
+use chrono::NaiveDateTime;
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+use sqlx::{MySql, Pool};
+
+pub struct DBCalendarFeed {
+    pub token: String,
+    pub user_id: String,
+    pub created_at: NaiveDateTime,
+}
+
+pub async fn get_calendar_feed(
+    db: &Pool<MySql>,
+    user_id: &str,
+) -> Result<Option<DBCalendarFeed>, anyhow::Error> {
+    let feed = sqlx::query_as!(
+        DBCalendarFeed,
+        "SELECT * FROM calendar_feeds WHERE user_id = ?",
+        user_id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(feed)
+}
+
+pub async fn get_calendar_feed_by_token(
+    db: &Pool<MySql>,
+    token: &str,
+) -> Result<Option<DBCalendarFeed>, anyhow::Error> {
+    let feed = sqlx::query_as!(
+        DBCalendarFeed,
+        "SELECT * FROM calendar_feeds WHERE token = ?",
+        token
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(feed)
+}
+
+// Gives the user a new feed token, the old one stops working
+pub async fn create_calendar_feed(
+    db: &Pool<MySql>,
+    user_id: &str,
+) -> Result<String, anyhow::Error> {
+    let mut token = [0u8; 32];
+    StdRng::from_entropy().fill_bytes(&mut token);
+    let token = hex::encode(token);
+
+    sqlx::query!(
+        "INSERT INTO calendar_feeds (token, user_id) VALUES (?, ?)
+        ON DUPLICATE KEY UPDATE token = VALUES(token), created_at = NOW()",
+        token,
+        user_id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(token)
+}
+
+pub async fn delete_calendar_feed(db: &Pool<MySql>, user_id: &str) -> Result<(), anyhow::Error> {
+    sqlx::query!("DELETE FROM calendar_feeds WHERE user_id = ?", user_id)
+        .execute(db)
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,116 @@
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use sqlx::{MySql, Pool, QueryBuilder};
+
+use crate::consts::MYSQL_PARAM_BIND_LIMIT;
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub enum DiscoverReason {
+    MaxDepth,
+    FranchiseCap,
+    // Related to an anime that was not crawled from a users list, eg. a refresh
+    Untracked,
+}
+
+impl From<DiscoverReason> for String {
+    fn from(val: DiscoverReason) -> Self {
+        let str = match val {
+            DiscoverReason::MaxDepth => "MAX_DEPTH",
+            DiscoverReason::FranchiseCap => "FRANCHISE_CAP",
+            DiscoverReason::Untracked => "UNTRACKED",
+        };
+
+        str.to_string()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct InsertDiscoveredAnime {
+    pub anime_id: u32,
+    pub from_anime_id: u32,
+    pub reason: DiscoverReason,
+    pub depth: u32,
+}
+
+#[derive(Serialize)]
+pub struct DBDiscoveredAnime {
+    pub anime_id: i32,
+    pub from_anime_id: i32,
+    pub reason: String,
+    pub depth: i32,
+    pub discovered_at: NaiveDateTime,
+}
+
+pub async fn insert_discovered_animes(
+    db: &Pool<MySql>,
+    animes: Vec<InsertDiscoveredAnime>,
+) -> Result<(), anyhow::Error> {
+    if animes.is_empty() {
+        return Ok(());
+    }
+
+    for group in animes.chunks(MYSQL_PARAM_BIND_LIMIT / 4) {
+        let mut query_builder: QueryBuilder<MySql> = QueryBuilder::new(
+            r#"
+            INSERT INTO discovered_animes (anime_id, from_anime_id, reason, depth)
+            "#,
+        );
+
+        query_builder.push_values(group.iter(), |mut b, anime| {
+            let reason: String = anime.reason.into();
+            b.push_bind(anime.anime_id)
+                .push_bind(anime.from_anime_id)
+                .push_bind(reason)
+                .push_bind(anime.depth);
+        });
+
+        query_builder.push(
+            r#"
+            ON DUPLICATE KEY UPDATE from_anime_id = VALUES(from_anime_id), reason = VALUES(reason), depth = VALUES(depth), discovered_at = NOW()
+            "#,
+        );
+
+        query_builder.build().execute(db).await?;
+    }
+
+    tracing::debug!("Recorded {} discovered animes", animes.len());
+
+    Ok(())
+}
+
+pub async fn get_discovered_animes(
+    db: &Pool<MySql>,
+) -> Result<Vec<DBDiscoveredAnime>, anyhow::Error> {
+    let rows = sqlx::query_as!(
+        DBDiscoveredAnime,
+        "SELECT * FROM discovered_animes ORDER BY discovered_at DESC"
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(rows)
+}
+
+pub async fn remove_discovered_animes(
+    db: &Pool<MySql>,
+    anime_ids: &[u32],
+) -> Result<(), anyhow::Error> {
+    if anime_ids.is_empty() {
+        return Ok(());
+    }
+
+    for group in anime_ids.chunks(MYSQL_PARAM_BIND_LIMIT) {
+        let mut query_builder: QueryBuilder<MySql> =
+            QueryBuilder::new("DELETE FROM discovered_animes WHERE anime_id IN (");
+
+        let mut separated = query_builder.separated(", ");
+        for id in group {
+            separated.push_bind(id);
+        }
+        separated.push_unseparated(")");
+
+        query_builder.build().execute(db).await?;
+    }
+
+    Ok(())
+}
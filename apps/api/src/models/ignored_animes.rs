@@ -0,0 +1,90 @@
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use sqlx::{MySql, Pool, QueryBuilder};
+
+use crate::consts::MYSQL_PARAM_BIND_LIMIT;
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub enum IgnoreReason {
+    AnilistNotFound,
+    ParseFailure,
+}
+
+impl From<IgnoreReason> for String {
+    fn from(val: IgnoreReason) -> Self {
+        let str = match val {
+            IgnoreReason::AnilistNotFound => "ANILIST_NOT_FOUND",
+            IgnoreReason::ParseFailure => "PARSE_FAILURE",
+        };
+
+        str.to_string()
+    }
+}
+
+#[derive(Serialize)]
+pub struct DBIgnoredAnime {
+    pub anime_id: i32,
+    pub reason: String,
+    pub message: Option<String>,
+    pub ignored_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+}
+
+pub async fn ignore_anime(
+    db: &Pool<MySql>,
+    anime_id: u32,
+    reason: IgnoreReason,
+    message: String,
+    expires_at: NaiveDateTime,
+) -> Result<(), anyhow::Error> {
+    let reason: String = reason.into();
+
+    // ignored_at is left alone on conflict so it keeps when the anime was first ignored
+    sqlx::query!(
+        "INSERT INTO ignored_animes (anime_id, reason, message, expires_at) VALUES (?, ?, ?, ?)
+        ON DUPLICATE KEY UPDATE reason = VALUES(reason), message = VALUES(message), expires_at = VALUES(expires_at)",
+        anime_id,
+        reason,
+        message,
+        expires_at
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_ignored_animes(db: &Pool<MySql>) -> Result<Vec<DBIgnoredAnime>, anyhow::Error> {
+    let rows = sqlx::query_as!(
+        DBIgnoredAnime,
+        "SELECT * FROM ignored_animes ORDER BY ignored_at"
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(rows)
+}
+
+pub async fn remove_ignored_animes(
+    db: &Pool<MySql>,
+    anime_ids: &[u32],
+) -> Result<(), anyhow::Error> {
+    if anime_ids.is_empty() {
+        return Ok(());
+    }
+
+    for group in anime_ids.chunks(MYSQL_PARAM_BIND_LIMIT) {
+        let mut query_builder: QueryBuilder<MySql> =
+            QueryBuilder::new("DELETE FROM ignored_animes WHERE anime_id IN (");
+
+        let mut separated = query_builder.separated(", ");
+        for id in group {
+            separated.push_bind(id);
+        }
+        separated.push_unseparated(")");
+
+        query_builder.build().execute(db).await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,324 @@
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use sqlx::prelude::FromRow;
+use sqlx::{MySql, Pool, QueryBuilder};
+
+use crate::consts::MYSQL_PARAM_BIND_LIMIT;
+use crate::importer::AnimeUserEntry;
+
+#[derive(Serialize, FromRow)]
+pub struct DBImportJob {
+    pub anime_id: i32,
+    pub user_id: String,
+    pub watch_status: Option<String>,
+    pub list_status: Option<String>,
+    pub status: String,
+    pub error: Option<String>,
+    pub attempts: i32,
+    pub next_attempt_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+// Jobs a batch can claim, finish or fail. Dead jobs of other users
+// for the same anime are left alone until an admin requeues them
+const LIVE_JOB_FILTER: &str = r#"status IN ("PENDING", "PROCESSING", "FAILED")"#;
+
+pub async fn enqueue_import_jobs(
+    db: &Pool<MySql>,
+    items: Vec<(u32, Vec<AnimeUserEntry>)>,
+) -> Result<(), anyhow::Error> {
+    // Animes queued without a user are stored with an empty user id
+    let rows: Vec<(u32, String, Option<String>, Option<String>)> = items
+        .into_iter()
+        .flat_map(|(anime_id, entries)| {
+            if entries.is_empty() {
+                return vec![(anime_id, "".to_string(), None, None)];
+            }
+
+            entries
+                .into_iter()
+                .map(|entry| {
+                    let list_status = serde_json::to_string(&entry.list_status).ok();
+                    (
+                        anime_id,
+                        entry.user_id,
+                        Some(entry.status.into()),
+                        list_status,
+                    )
+                })
+                .collect()
+        })
+        .collect();
+
+    if rows.is_empty() {
+        return Ok(());
+    }
+
+    for group in rows.chunks(MYSQL_PARAM_BIND_LIMIT / 4) {
+        let mut query_builder: QueryBuilder<MySql> = QueryBuilder::new(
+            r#"
+            INSERT INTO import_jobs (anime_id, user_id, watch_status, list_status)
+            "#,
+        );
+
+        query_builder.push_values(group.iter(), |mut b, row| {
+            b.push_bind(row.0)
+                .push_bind(row.1.clone())
+                .push_bind(row.2.clone())
+                .push_bind(row.3.clone());
+        });
+
+        query_builder.push(
+            r#"
+            ON DUPLICATE KEY UPDATE watch_status = VALUES(watch_status), list_status = VALUES(list_status), attempts = IF(status = "DEAD", 0, attempts), status = "PENDING", updated_at = NOW()
+            "#,
+        );
+
+        query_builder.build().execute(db).await?;
+    }
+
+    tracing::debug!("Persisted {} import jobs", rows.len());
+
+    Ok(())
+}
+
+pub async fn claim_import_jobs(db: &Pool<MySql>, anime_ids: &[u32]) -> Result<(), anyhow::Error> {
+    update_import_jobs(db, anime_ids, "PROCESSING", None).await
+}
+
+// Records a failed attempt, the job is tried again after `next_attempt_at`
+pub async fn retry_import_jobs(
+    db: &Pool<MySql>,
+    anime_ids: &[u32],
+    attempts: u32,
+    error: String,
+    next_attempt_at: NaiveDateTime,
+) -> Result<(), anyhow::Error> {
+    if anime_ids.is_empty() {
+        return Ok(());
+    }
+
+    // Every row of an anime gets the importers count, rows
+    // added after earlier failures would otherwise lag behind
+    for group in anime_ids.chunks(MYSQL_PARAM_BIND_LIMIT - 3) {
+        let mut query_builder: QueryBuilder<MySql> =
+            QueryBuilder::new(r#"UPDATE import_jobs SET status = "FAILED", attempts = "#);
+        query_builder
+            .push_bind(attempts)
+            .push(", error = ")
+            .push_bind(error.clone())
+            .push(", next_attempt_at = ")
+            .push_bind(next_attempt_at)
+            .push(", updated_at = NOW() WHERE ")
+            .push(LIVE_JOB_FILTER)
+            .push(" AND anime_id IN (");
+
+        let mut separated = query_builder.separated(", ");
+        for id in group {
+            separated.push_bind(id);
+        }
+        separated.push_unseparated(")");
+
+        query_builder.build().execute(db).await?;
+    }
+
+    Ok(())
+}
+
+pub async fn dead_letter_import_jobs(
+    db: &Pool<MySql>,
+    anime_ids: &[u32],
+    error: String,
+) -> Result<(), anyhow::Error> {
+    update_import_jobs(db, anime_ids, "DEAD", Some(error)).await
+}
+
+// Parks the jobs of an ignored anime until `until`, when the ignore expires
+pub async fn ignore_import_jobs(
+    db: &Pool<MySql>,
+    anime_ids: &[u32],
+    error: String,
+    until: NaiveDateTime,
+) -> Result<(), anyhow::Error> {
+    if anime_ids.is_empty() {
+        return Ok(());
+    }
+
+    for group in anime_ids.chunks(MYSQL_PARAM_BIND_LIMIT - 2) {
+        let mut query_builder: QueryBuilder<MySql> =
+            QueryBuilder::new(r#"UPDATE import_jobs SET status = "IGNORED", error = "#);
+        query_builder
+            .push_bind(error.clone())
+            .push(", next_attempt_at = ")
+            .push_bind(until)
+            .push(", updated_at = NOW() WHERE ")
+            .push(LIVE_JOB_FILTER)
+            .push(" AND anime_id IN (");
+
+        let mut separated = query_builder.separated(", ");
+        for id in group {
+            separated.push_bind(id);
+        }
+        separated.push_unseparated(")");
+
+        query_builder.build().execute(db).await?;
+    }
+
+    Ok(())
+}
+
+// Moves dead or ignored jobs back to pending with their attempts reset
+pub async fn requeue_import_jobs(
+    db: &Pool<MySql>,
+    anime_ids: &[u32],
+    status: &str,
+) -> Result<(), anyhow::Error> {
+    if anime_ids.is_empty() {
+        return Ok(());
+    }
+
+    for group in anime_ids.chunks(MYSQL_PARAM_BIND_LIMIT - 1) {
+        let mut query_builder: QueryBuilder<MySql> = QueryBuilder::new(
+            r#"
+            UPDATE import_jobs SET status = "PENDING", attempts = 0, error = NULL, next_attempt_at = NULL, updated_at = NOW()
+            WHERE (status = "#,
+        );
+        // Live rows of the same animes are reset too so they
+        // all agree on the attempts with the importer
+        query_builder
+            .push_bind(status)
+            .push(" OR ")
+            .push(LIVE_JOB_FILTER)
+            .push(") AND anime_id IN (");
+
+        let mut separated = query_builder.separated(", ");
+        for id in group {
+            separated.push_bind(id);
+        }
+        separated.push_unseparated(")");
+
+        query_builder.build().execute(db).await?;
+    }
+
+    Ok(())
+}
+
+pub async fn complete_import_jobs(
+    db: &Pool<MySql>,
+    anime_ids: &[u32],
+) -> Result<(), anyhow::Error> {
+    if anime_ids.is_empty() {
+        return Ok(());
+    }
+
+    for group in anime_ids.chunks(MYSQL_PARAM_BIND_LIMIT) {
+        let mut query_builder: QueryBuilder<MySql> =
+            QueryBuilder::new("DELETE FROM import_jobs WHERE ");
+        query_builder
+            .push(LIVE_JOB_FILTER)
+            .push(" AND anime_id IN (");
+
+        let mut separated = query_builder.separated(", ");
+        for id in group {
+            separated.push_bind(id);
+        }
+        separated.push_unseparated(")");
+
+        query_builder.build().execute(db).await?;
+    }
+
+    Ok(())
+}
+
+async fn update_import_jobs(
+    db: &Pool<MySql>,
+    anime_ids: &[u32],
+    status: &str,
+    error: Option<String>,
+) -> Result<(), anyhow::Error> {
+    if anime_ids.is_empty() {
+        return Ok(());
+    }
+
+    for group in anime_ids.chunks(MYSQL_PARAM_BIND_LIMIT - 2) {
+        let mut query_builder: QueryBuilder<MySql> =
+            QueryBuilder::new("UPDATE import_jobs SET status = ");
+        query_builder
+            .push_bind(status)
+            .push(", error = ")
+            .push_bind(error.clone())
+            .push(", updated_at = NOW() WHERE ")
+            .push(LIVE_JOB_FILTER)
+            .push(" AND anime_id IN (");
+
+        let mut separated = query_builder.separated(", ");
+        for id in group {
+            separated.push_bind(id);
+        }
+        separated.push_unseparated(")");
+
+        query_builder.build().execute(db).await?;
+    }
+
+    Ok(())
+}
+
+pub async fn get_unfinished_import_jobs(
+    db: &Pool<MySql>,
+) -> Result<Vec<DBImportJob>, anyhow::Error> {
+    let rows = sqlx::query_as!(
+        DBImportJob,
+        r#"SELECT * FROM import_jobs WHERE status NOT IN ("DEAD", "IGNORED") ORDER BY created_at"#
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(rows)
+}
+
+pub async fn get_dead_import_jobs(db: &Pool<MySql>) -> Result<Vec<DBImportJob>, anyhow::Error> {
+    let rows = sqlx::query_as!(
+        DBImportJob,
+        r#"SELECT * FROM import_jobs WHERE status = "DEAD" ORDER BY updated_at"#
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(rows)
+}
+
+pub async fn get_ignored_import_jobs(
+    db: &Pool<MySql>,
+    anime_ids: &[u32],
+) -> Result<Vec<DBImportJob>, anyhow::Error> {
+    let mut jobs = vec![];
+
+    for group in anime_ids.chunks(MYSQL_PARAM_BIND_LIMIT) {
+        let mut query_builder: QueryBuilder<MySql> = QueryBuilder::new(
+            r#"
+            SELECT
+                anime_id, user_id,
+                CAST(watch_status AS CHAR) AS watch_status,
+                list_status,
+                CAST(status AS CHAR) AS status,
+                error, attempts, next_attempt_at, created_at, updated_at
+            FROM import_jobs WHERE status = "IGNORED" AND anime_id IN ("#,
+        );
+
+        let mut separated = query_builder.separated(", ");
+        for id in group {
+            separated.push_bind(id);
+        }
+        separated.push_unseparated(") ORDER BY created_at");
+
+        jobs.extend(
+            query_builder
+                .build_query_as::<DBImportJob>()
+                .fetch_all(db)
+                .await?,
+        );
+    }
+
+    Ok(jobs)
+}
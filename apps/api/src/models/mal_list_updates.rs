@@ -0,0 +1,170 @@
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use sqlx::{MySql, Pool};
+
+use crate::importer::AnimeWatchStatus;
+use crate::mal::MalListStatusUpdate;
+
+#[derive(Serialize)]
+pub struct DBMalListUpdate {
+    pub user_id: String,
+    pub anime_id: i32,
+    pub status: Option<String>,
+    pub score: Option<i32>,
+    pub num_episodes_watched: Option<i32>,
+    pub attempts: i32,
+    pub next_attempt_at: NaiveDateTime,
+    pub error: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+impl DBMalListUpdate {
+    pub fn update(&self) -> MalListStatusUpdate {
+        MalListStatusUpdate {
+            status: self.status.clone().map(AnimeWatchStatus::from),
+            score: self.score.map(|score| score as u32),
+            num_episodes_watched: self.num_episodes_watched.map(|episodes| episodes as u32),
+        }
+    }
+}
+
+// Queues `update` to be sent to MAL, merging it into any change
+// to the same entry that has not been sent yet
+pub async fn enqueue_mal_list_update(
+    db: &Pool<MySql>,
+    user_id: &str,
+    anime_id: u32,
+    update: &MalListStatusUpdate,
+) -> Result<(), anyhow::Error> {
+    let status: Option<String> = update.status.clone().map(String::from);
+    sqlx::query!(
+        "INSERT INTO mal_list_updates (user_id, anime_id, status, score, num_episodes_watched)
+        VALUES (?, ?, ?, ?, ?)
+        ON DUPLICATE KEY UPDATE
+        status = COALESCE(VALUES(status), status),
+        score = COALESCE(VALUES(score), score),
+        num_episodes_watched = COALESCE(VALUES(num_episodes_watched), num_episodes_watched),
+        attempts = 0, next_attempt_at = NOW(), error = NULL, updated_at = NOW()",
+        user_id,
+        anime_id,
+        status,
+        update.score,
+        update.num_episodes_watched
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_mal_list_update(
+    db: &Pool<MySql>,
+    user_id: &str,
+    anime_id: u32,
+) -> Result<Option<DBMalListUpdate>, anyhow::Error> {
+    let row = sqlx::query_as!(
+        DBMalListUpdate,
+        "SELECT * FROM mal_list_updates WHERE user_id = ? AND anime_id = ?",
+        user_id,
+        anime_id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(row)
+}
+
+// Changes that are due to be sent, oldest first. Changes of users who have
+// to log in to MAL again wait until they do, there is no token to send them with
+pub async fn get_due_mal_list_updates(
+    db: &Pool<MySql>,
+    limit: u32,
+) -> Result<Vec<DBMalListUpdate>, anyhow::Error> {
+    let rows = sqlx::query_as!(
+        DBMalListUpdate,
+        "SELECT mal_list_updates.* FROM mal_list_updates
+        JOIN users ON users.id = mal_list_updates.user_id
+        WHERE mal_list_updates.next_attempt_at <= NOW() AND users.mal_relogin_required = false
+        ORDER BY mal_list_updates.next_attempt_at LIMIT ?",
+        limit
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(rows)
+}
+
+// Anime ids of a users entries with changes that have not reached MAL yet
+pub async fn get_pending_mal_list_update_ids(
+    db: &Pool<MySql>,
+    user_id: &str,
+) -> Result<Vec<i32>, anyhow::Error> {
+    let ids = sqlx::query_scalar!(
+        "SELECT anime_id FROM mal_list_updates WHERE user_id = ?",
+        user_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(ids)
+}
+
+// Removes a change once it has been sent. Changes merged in
+// since `sent` was read are left to be sent next
+pub async fn complete_mal_list_update(
+    db: &Pool<MySql>,
+    sent: &DBMalListUpdate,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        "DELETE FROM mal_list_updates WHERE user_id = ? AND anime_id = ?
+        AND status <=> ? AND score <=> ? AND num_episodes_watched <=> ?",
+        sent.user_id,
+        sent.anime_id,
+        sent.status,
+        sent.score,
+        sent.num_episodes_watched
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn retry_mal_list_update(
+    db: &Pool<MySql>,
+    user_id: &str,
+    anime_id: i32,
+    next_attempt_at: NaiveDateTime,
+    error: String,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        "UPDATE mal_list_updates SET attempts = attempts + 1, next_attempt_at = ?, error = ?,
+        updated_at = NOW() WHERE user_id = ? AND anime_id = ?",
+        next_attempt_at,
+        error,
+        user_id,
+        anime_id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+// Drops a change MAL will never accept
+pub async fn delete_mal_list_update(
+    db: &Pool<MySql>,
+    user_id: &str,
+    anime_id: i32,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        "DELETE FROM mal_list_updates WHERE user_id = ? AND anime_id = ?",
+        user_id,
+        anime_id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
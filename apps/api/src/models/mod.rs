@@ -1,4 +1,12 @@
+pub mod airing_schedules;
 pub mod anime;
+pub mod anime_details;
 pub mod anime_relations;
+pub mod anime_series;
 pub mod anime_users;
+pub mod calendar_feeds;
+pub mod discovered_animes;
+pub mod ignored_animes;
+pub mod import_jobs;
+pub mod mal_list_updates;
 pub mod user;
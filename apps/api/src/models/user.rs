@@ -1,28 +1,28 @@
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
+use sqlx::{MySql, Pool};
 
 use crate::auth::session::Session;
 use crate::AppState;
 
 pub async fn create_user(app_state: AppState, user: CreateUser) -> DBUser {
     let id = cuid::cuid2();
-    let res = sqlx::query!(
+    sqlx::query!(
         "INSERT INTO users
-        (id,name,picture, mal_id, mal_access_token, mal_refresh_token)
-        VALUES (?,?,?,?,?,?)",
+        (id,name,picture, mal_id, mal_access_token, mal_refresh_token, mal_token_expires_at)
+        VALUES (?,?,?,?,?,?,?)",
         id,
         user.name,
         user.picture,
         user.mal_id,
         user.mal_access_token,
-        user.mal_refresh_token
+        user.mal_refresh_token,
+        user.mal_token_expires_at
     )
     .execute(&app_state.db)
     .await
     .expect("Failed to create user");
 
-    let id = res.last_insert_id();
-
     sqlx::query_as!(DBUser, "SELECT * FROM users WHERE id = ?", id)
         .fetch_one(&app_state.db)
         .await
@@ -34,10 +34,7 @@ pub async fn find_user_mal_id(state: AppState, mal_id: i32) -> Option<DBUser> {
         .fetch_one(&state.db)
         .await;
 
-    match user {
-        Ok(user) => Some(user),
-        Err(_) => None,
-    }
+    user.ok()
 }
 
 pub async fn get_user_by_session(state: AppState, session_id: String) -> Option<DBUser> {
@@ -52,6 +49,14 @@ pub async fn get_user_by_session(state: AppState, session_id: String) -> Option<
         .ok()
 }
 
+pub async fn get_user(db: &Pool<MySql>, user_id: &str) -> Result<Option<DBUser>, anyhow::Error> {
+    let user = sqlx::query_as!(DBUser, "SELECT * FROM users WHERE id = ?", user_id)
+        .fetch_optional(db)
+        .await?;
+
+    Ok(user)
+}
+
 pub async fn get_mal_user(state: AppState, token: String, mal_id: i32) -> MalUser {
     let mut search_id = "@me".to_string();
 
@@ -61,10 +66,7 @@ pub async fn get_mal_user(state: AppState, token: String, mal_id: i32) -> MalUse
 
     state
         .reqwest
-        .get(format!(
-            "https://api.myanimelist.net/v2/users/{}",
-            search_id
-        ))
+        .get(format!("{}/users/{}", state.urls.mal_api, search_id))
         .bearer_auth(token)
         .send()
         .await
@@ -74,12 +76,60 @@ pub async fn get_mal_user(state: AppState, token: String, mal_id: i32) -> MalUse
         .expect("Failed to parse MAL user")
 }
 
+// Stores new MAL tokens, clearing any need to log in again
+pub async fn update_mal_tokens(
+    db: &Pool<MySql>,
+    user_id: &str,
+    access_token: &str,
+    refresh_token: &str,
+    expires_at: Option<NaiveDateTime>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        "UPDATE users SET
+        mal_access_token = ?, mal_refresh_token = ?, mal_token_expires_at = ?,
+        mal_relogin_required = false, updated_at = NOW()
+        WHERE id = ?",
+        access_token,
+        refresh_token,
+        expires_at,
+        user_id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn flag_mal_relogin(db: &Pool<MySql>, user_id: &str) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        "UPDATE users SET mal_relogin_required = true, updated_at = NOW() WHERE id = ?",
+        user_id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+// Records that the users list was just synced with MAL
+pub async fn set_list_last_update(db: &Pool<MySql>, user_id: &str) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        "UPDATE users SET list_last_update = NOW() WHERE id = ?",
+        user_id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
 pub struct CreateUser {
     pub name: String,
     pub picture: String,
     pub mal_id: i32,
     pub mal_access_token: String,
     pub mal_refresh_token: String,
+    pub mal_token_expires_at: Option<NaiveDateTime>,
 }
 
 #[derive(Deserialize, Serialize, Clone)]
@@ -90,6 +140,8 @@ pub struct DBUser {
     pub mal_id: i32,
     pub mal_access_token: String,
     pub mal_refresh_token: String,
+    pub mal_token_expires_at: Option<NaiveDateTime>,
+    pub mal_relogin_required: bool,
     pub list_last_update: NaiveDateTime,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
@@ -109,12 +161,14 @@ pub struct SafeUser {
     pub name: String,
     pub picture: String,
     pub mal_id: i32,
+    pub mal_relogin_required: bool,
     pub created_at: NaiveDateTime,
 }
 
 impl From<DBUser> for SafeUser {
     fn from(user: DBUser) -> Self {
         SafeUser {
+            mal_relogin_required: user.mal_relogin_required,
             created_at: user.created_at,
             mal_id: user.mal_id,
             picture: user.picture,
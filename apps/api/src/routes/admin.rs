@@ -0,0 +1,81 @@
+use axum::extract::{Path, State};
+use axum::Json;
+use axum::{http::StatusCode, response::IntoResponse};
+use serde_json::json;
+
+use crate::helpers::json_response;
+use crate::models::discovered_animes::get_discovered_animes;
+use crate::models::ignored_animes::get_ignored_animes;
+use crate::models::import_jobs::get_dead_import_jobs;
+use crate::{AppError, AppState};
+
+#[axum::debug_handler]
+pub async fn get_ignored(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+    let ignored = get_ignored_animes(&state.db).await?;
+
+    Ok(json_response!(StatusCode::OK, {
+        "ignored": ignored
+    }))
+}
+
+#[axum::debug_handler]
+pub async fn clear_ignored(State(state): State<AppState>) -> impl IntoResponse {
+    state.importer.unignore_all();
+
+    StatusCode::NO_CONTENT
+}
+
+#[axum::debug_handler]
+pub async fn clear_ignored_anime(
+    State(state): State<AppState>,
+    Path(anime_id): Path<u32>,
+) -> impl IntoResponse {
+    state.importer.unignore(vec![anime_id]);
+
+    StatusCode::NO_CONTENT
+}
+
+#[axum::debug_handler]
+pub async fn cancel_queued_anime(
+    State(state): State<AppState>,
+    Path(anime_id): Path<u32>,
+) -> impl IntoResponse {
+    state.importer.cancel(vec![anime_id]);
+
+    StatusCode::NO_CONTENT
+}
+
+#[axum::debug_handler]
+pub async fn get_dead_letter(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+    let jobs = get_dead_import_jobs(&state.db).await?;
+
+    Ok(json_response!(StatusCode::OK, {
+        "jobs": jobs
+    }))
+}
+
+#[axum::debug_handler]
+pub async fn requeue_dead_letter(State(state): State<AppState>) -> impl IntoResponse {
+    state.importer.requeue_all_dead();
+
+    StatusCode::NO_CONTENT
+}
+
+#[axum::debug_handler]
+pub async fn requeue_dead_letter_anime(
+    State(state): State<AppState>,
+    Path(anime_id): Path<u32>,
+) -> impl IntoResponse {
+    state.importer.requeue_dead(vec![anime_id]);
+
+    StatusCode::NO_CONTENT
+}
+
+#[axum::debug_handler]
+pub async fn get_discovered(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+    let discovered = get_discovered_animes(&state.db).await?;
+
+    Ok(json_response!(StatusCode::OK, {
+        "discovered": discovered
+    }))
+}
@@ -15,8 +15,9 @@ use serde::Deserialize;
 
 use crate::{
     auth::session::create_session,
-    importer::{AnimeUserEntry, AnimeWatchStatus},
-    models::user::{create_user, find_user_mal_id, get_mal_user, CreateUser},
+    mal::sync::sync_mal_list,
+    mal::token::token_expires_at,
+    models::user::{create_user, find_user_mal_id, get_mal_user, update_mal_tokens, CreateUser},
 };
 use crate::{mal::get_mal_user_list, AppState};
 
@@ -56,9 +57,6 @@ pub async fn handle_mal_callback(
     jar: CookieJar,
     Extension(oauth_client): Extension<BasicClient>,
 ) -> impl IntoResponse {
-    let csrf_token = private_jar
-        .get("mal_csrf_token")
-        .expect("No CSRF token in cookie jar");
     let pkce_verifier_str = private_jar
         .get("mal_pkce_verifier")
         .expect("No PKCE verifier in cookie jar");
@@ -74,6 +72,11 @@ pub async fn handle_mal_callback(
         .unwrap();
 
     let token = token_result.access_token().secret().to_string();
+    let refresh_token = token_result
+        .refresh_token()
+        .map(|token| token.secret().to_string())
+        .unwrap_or_default();
+    let expires_at = token_expires_at(&token_result);
     let mal_user = get_mal_user(state.clone(), token.clone(), 0).await;
     let mal_user_id = mal_user.id;
 
@@ -81,16 +84,14 @@ pub async fn handle_mal_callback(
 
     let user = match user {
         Some(mut user) => {
-            // Ensure the user has the latest token
-            sqlx::query!(
-                "UPDATE users SET mal_access_token = ? WHERE id = ?",
-                token,
-                user.id
-            )
-            .execute(&state.db)
-            .await
-            .expect("Failed to update user token");
+            // Ensure the user has the latest tokens
+            update_mal_tokens(&state.db, &user.id, &token, &refresh_token, expires_at)
+                .await
+                .expect("Failed to update user token");
             user.mal_access_token = token;
+            user.mal_refresh_token = refresh_token;
+            user.mal_token_expires_at = expires_at;
+            user.mal_relogin_required = false;
             user
         }
         None => {
@@ -101,7 +102,8 @@ pub async fn handle_mal_callback(
                     picture: mal_user.picture,
                     mal_id: mal_user.id,
                     mal_access_token: token.clone(),
-                    mal_refresh_token: "".to_string(),
+                    mal_refresh_token: refresh_token,
+                    mal_token_expires_at: expires_at,
                 },
             )
             .await
@@ -115,26 +117,14 @@ pub async fn handle_mal_callback(
     let updated_jar = jar.add(cookie);
 
     let reqwest = state.reqwest.clone();
-    let mal_user_list = get_mal_user_list(reqwest, user).await;
+    let mal_user_list =
+        get_mal_user_list(reqwest, &state.urls.mal_api, user.mal_access_token).await;
 
     match mal_user_list {
         Ok(mal) => {
-            let ids = mal
-                .data
-                .iter()
-                .map(|item| AnimeUserEntry {
-                    status: item
-                        .list_status
-                        .status
-                        .parse::<AnimeWatchStatus>()
-                        .map_err(|_| AnimeWatchStatus::Watching)
-                        .expect("Failed to parse watch status"),
-                    user_id: user_id.clone(),
-                    anime_id: item.node.id,
-                })
-                .collect::<Vec<_>>();
-            let mut importer = state.importer.lock().await;
-            importer.add_all(ids);
+            if let Err(err) = sync_mal_list(&state, &user_id, mal).await {
+                tracing::error!("Failed to sync MAL list for user {}: {:?}", user_id, err);
+            }
         }
         Err(err) => {
             // TODO: Handle better?
@@ -0,0 +1,130 @@
+use axum::extract::{Path, State};
+use axum::http::header;
+use axum::Json;
+use axum::{http::StatusCode, response::IntoResponse, Extension};
+use chrono::{Duration, Utc};
+use serde_json::json;
+
+use crate::calendar::render_calendar;
+use crate::helpers::json_response;
+use crate::models::airing_schedules::get_user_schedule;
+use crate::models::calendar_feeds::{
+    create_calendar_feed, delete_calendar_feed, get_calendar_feed, get_calendar_feed_by_token,
+};
+use crate::models::user::DBUser;
+use crate::AppState;
+
+// How far ahead the feed lists episodes
+const FEED_DAYS: i64 = 90;
+
+fn feed_url(state: &AppState, token: &str) -> String {
+    format!("{}/calendar/{}.ics", state.api_url, token)
+}
+
+#[axum::debug_handler]
+pub async fn get_calendar(
+    State(state): State<AppState>,
+    Extension(user): Extension<DBUser>,
+) -> impl IntoResponse {
+    match get_calendar_feed(&state.db, &user.id).await {
+        Ok(Some(feed)) => json_response!(StatusCode::OK, {
+            "url": feed_url(&state, &feed.token),
+            "created_at": feed.created_at
+        }),
+        Ok(None) => json_response!(StatusCode::OK, {
+            "url": null
+        }),
+        Err(err) => {
+            tracing::error!("Failed to get calendar feed: {:?}", err);
+            json_response!(StatusCode::INTERNAL_SERVER_ERROR, {
+                "error": "Failed to get calendar feed"
+            })
+        }
+    }
+}
+
+// Creates the users feed, or replaces its token if it already has one
+#[axum::debug_handler]
+pub async fn create_calendar(
+    State(state): State<AppState>,
+    Extension(user): Extension<DBUser>,
+) -> impl IntoResponse {
+    match create_calendar_feed(&state.db, &user.id).await {
+        Ok(token) => json_response!(StatusCode::CREATED, {
+            "url": feed_url(&state, &token)
+        }),
+        Err(err) => {
+            tracing::error!("Failed to create calendar feed: {:?}", err);
+            json_response!(StatusCode::INTERNAL_SERVER_ERROR, {
+                "error": "Failed to create calendar feed"
+            })
+        }
+    }
+}
+
+#[axum::debug_handler]
+pub async fn revoke_calendar(
+    State(state): State<AppState>,
+    Extension(user): Extension<DBUser>,
+) -> impl IntoResponse {
+    match delete_calendar_feed(&state.db, &user.id).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(err) => {
+            tracing::error!("Failed to revoke calendar feed: {:?}", err);
+            json_response!(StatusCode::INTERNAL_SERVER_ERROR, {
+                "error": "Failed to revoke calendar feed"
+            })
+        }
+    }
+}
+
+// Upcoming episodes of releasing animes the user is watching or plans to watch.
+// Authenticated by the token in the url since calendar apps do not have the session cookie
+#[axum::debug_handler]
+pub async fn get_calendar_feed_ics(
+    State(state): State<AppState>,
+    Path(file): Path<String>,
+) -> impl IntoResponse {
+    let Some(token) = file.strip_suffix(".ics") else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let feed = match get_calendar_feed_by_token(&state.db, token).await {
+        Ok(Some(feed)) => feed,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(err) => {
+            tracing::error!("Failed to get calendar feed: {:?}", err);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let now = Utc::now();
+    let episodes = match get_user_schedule(
+        &state.db,
+        &feed.user_id,
+        now,
+        now + Duration::days(FEED_DAYS),
+    )
+    .await
+    {
+        Ok(episodes) => episodes,
+        Err(err) => {
+            tracing::error!("Failed to get schedule for calendar feed: {:?}", err);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let episodes: Vec<_> = episodes
+        .into_iter()
+        .filter(|episode| episode.anime_status == "RELEASING")
+        .collect();
+
+    (
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "text/calendar; charset=utf-8"),
+            (header::CACHE_CONTROL, "private, max-age=3600"),
+        ],
+        render_calendar("Sei airing schedule", &episodes),
+    )
+        .into_response()
+}
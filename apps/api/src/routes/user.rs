@@ -1,17 +1,30 @@
-use axum::extract::State;
+use std::convert::Infallible;
+
+use axum::extract::{Path, Query, State};
+use axum::response::sse::{Event, KeepAlive, Sse};
 use axum::Json;
 use axum::{http::StatusCode, response::IntoResponse, Extension};
-use chrono::{Duration, Utc};
-use serde::Serialize;
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, Utc};
+use chrono_tz::Tz;
+use oauth2::basic::BasicClient;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
 
 use crate::helpers::json_response;
-use crate::importer::{AnimeUserEntry, AnimeWatchStatus};
-use crate::mal::get_mal_user_list;
+use crate::importer::AnimeWatchStatus;
+use crate::mal::outbox::{push_mal_list_update, PushOutcome};
+use crate::mal::sync::sync_mal_list;
+use crate::mal::token::with_mal_token;
+use crate::mal::{get_mal_user_list, MalListStatusUpdate};
+use crate::models::airing_schedules::{get_user_schedule, DBScheduledEpisode};
 use crate::models::anime::get_released_animes_by_id;
 use crate::models::anime_users::{
-    get_user_entrys, link_user_to_anime, update_watch_priority, DBAnimeUser, WatchPriorityUpdate,
+    get_user_entry, get_user_entrys, update_user_entry, update_watch_priority, DBAnimeUser,
+    WatchPriorityUpdate,
 };
+use crate::models::mal_list_updates::enqueue_mal_list_update;
 use crate::models::user::{DBUser, SafeUser};
 use crate::AppState;
 
@@ -26,44 +39,64 @@ struct SingleEntry {
     anime_id: u32,
     watch_status: String,
     watch_priority: u32,
+    score: u32,
+    num_episodes_watched: u32,
+    is_rewatching: bool,
+    start_date: Option<NaiveDate>,
+    finish_date: Option<NaiveDate>,
+    // MALs priority, 0 = low to 2 = high
+    mal_priority: u32,
+    comments: String,
+    // When the entry last changed on MAL
+    mal_updated_at: Option<NaiveDateTime>,
+}
+
+impl From<&DBAnimeUser> for SingleEntry {
+    fn from(entry: &DBAnimeUser) -> Self {
+        SingleEntry {
+            anime_id: entry.anime_id as u32,
+            watch_priority: entry.watch_priority as u32,
+            watch_status: entry.status.clone().into(),
+            score: entry.score as u32,
+            num_episodes_watched: entry.num_episodes_watched as u32,
+            is_rewatching: entry.is_rewatching,
+            start_date: entry.start_date,
+            finish_date: entry.finish_date,
+            mal_priority: entry.mal_priority as u32,
+            comments: entry.comments.clone().unwrap_or_default(),
+            mal_updated_at: entry.mal_updated_at,
+        }
+    }
 }
 
 #[axum::debug_handler]
 pub async fn get_list(
     State(state): State<AppState>,
     Extension(user): Extension<DBUser>,
+    Extension(oauth_client): Extension<BasicClient>,
 ) -> impl IntoResponse {
     let user_id = user.id.clone();
 
     let now = Utc::now().naive_utc();
     let five_minutes_ago = now - Duration::minutes(5);
 
-    if user.list_last_update < five_minutes_ago {
+    // Nothing can be fetched until the user logs in again
+    if user.list_last_update < five_minutes_ago && !user.mal_relogin_required {
         // Update list in background
-        let user = user.clone();
+        let mut user = user.clone();
         let user_id = user.id.clone();
         let state = state.clone();
         tokio::spawn(async move {
-            let mal_user_list = get_mal_user_list(state.reqwest, user).await;
+            let mal_user_list = with_mal_token(&state.db, &oauth_client, &mut user, |token| {
+                get_mal_user_list(state.reqwest.clone(), &state.urls.mal_api, token)
+            })
+            .await;
 
             match mal_user_list {
                 Ok(mal) => {
-                    let ids = mal
-                        .data
-                        .iter()
-                        .map(|item| AnimeUserEntry {
-                            status: item
-                                .list_status
-                                .status
-                                .parse::<AnimeWatchStatus>()
-                                .map_err(|_| AnimeWatchStatus::Watching)
-                                .expect("Failed to parse watch status"),
-                            user_id: user_id.clone(),
-                            anime_id: item.node.id,
-                        })
-                        .collect::<Vec<_>>();
-                    let mut importer = state.importer.lock().await;
-                    importer.add_all(ids);
+                    if let Err(err) = sync_mal_list(&state, &user_id, mal).await {
+                        tracing::error!("Failed to sync MAL list for user {}: {:?}", user_id, err);
+                    }
                 }
                 Err(err) => {
                     // TODO: Handle better?
@@ -80,14 +113,7 @@ pub async fn get_list(
     let animes = get_released_animes_by_id(&state.db, anime_ids)
         .await
         .unwrap();
-    let entries = entries
-        .iter()
-        .map(|entry| SingleEntry {
-            anime_id: entry.anime_id as u32,
-            watch_priority: entry.watch_priority as u32,
-            watch_status: entry.status.clone().into(),
-        })
-        .collect::<Vec<_>>();
+    let entries = entries.iter().map(SingleEntry::from).collect::<Vec<_>>();
 
     json_response!(StatusCode::OK, {
         "animes": animes,
@@ -104,3 +130,209 @@ pub async fn update_list_order(
     update_watch_priority(&state.db, user.id, data).await;
     StatusCode::CREATED
 }
+
+#[derive(Deserialize)]
+pub struct ListEntryUpdate {
+    // One of MALs list statuses, eg. "plan_to_watch"
+    status: Option<String>,
+    score: Option<u32>,
+    num_episodes_watched: Option<u32>,
+}
+
+// Highest score MAL allows
+const MAX_SCORE: u32 = 10;
+
+// Changes an entry on the users list, then sends the change to MAL.
+// If MAL can not be reached the change is kept and sent later
+#[axum::debug_handler]
+pub async fn update_list_entry(
+    State(state): State<AppState>,
+    Extension(mut user): Extension<DBUser>,
+    Extension(oauth_client): Extension<BasicClient>,
+    Path(anime_id): Path<u32>,
+    Json(data): Json<ListEntryUpdate>,
+) -> impl IntoResponse {
+    let status = match data.status.map(|status| status.parse::<AnimeWatchStatus>()) {
+        Some(Ok(status)) => Some(status),
+        Some(Err(_)) => {
+            return json_response!(StatusCode::BAD_REQUEST, {
+                "error": "Invalid status"
+            })
+        }
+        None => None,
+    };
+    if data.score.is_some_and(|score| score > MAX_SCORE) {
+        return json_response!(StatusCode::BAD_REQUEST, {
+            "error": "score must be between 0 and 10"
+        });
+    }
+    let update = MalListStatusUpdate {
+        status,
+        score: data.score,
+        num_episodes_watched: data.num_episodes_watched,
+    };
+
+    match get_user_entry(&state.db, &user.id, anime_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return json_response!(StatusCode::NOT_FOUND, {
+                "error": "Anime is not on the list"
+            })
+        }
+        Err(err) => {
+            tracing::error!("Failed to get list entry: {:?}", err);
+            return json_response!(StatusCode::INTERNAL_SERVER_ERROR, {
+                "error": "Failed to update list entry"
+            });
+        }
+    }
+
+    let saved = async {
+        update_user_entry(&state.db, &user.id, anime_id, &update).await?;
+        enqueue_mal_list_update(&state.db, &user.id, anime_id, &update).await?;
+        get_user_entry(&state.db, &user.id, anime_id).await
+    }
+    .await;
+    let entry = match saved {
+        Ok(Some(entry)) => entry,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(err) => {
+            tracing::error!("Failed to update list entry: {:?}", err);
+            return json_response!(StatusCode::INTERNAL_SERVER_ERROR, {
+                "error": "Failed to update list entry"
+            });
+        }
+    };
+
+    // The change is saved either way, so a failure to send it is only logged
+    let mal_sync = push_mal_list_update(&state, &oauth_client, &mut user, anime_id)
+        .await
+        .unwrap_or_else(|err| {
+            tracing::error!("Failed to send list update to MAL: {:?}", err);
+            PushOutcome::Queued
+        });
+
+    json_response!(StatusCode::OK, {
+        "entry": SingleEntry::from(&entry),
+        "mal_sync": mal_sync
+    })
+}
+
+#[axum::debug_handler]
+pub async fn get_import_status(
+    State(state): State<AppState>,
+    Extension(user): Extension<DBUser>,
+) -> impl IntoResponse {
+    let progress = state.importer.progress(user.id).await.unwrap_or_default();
+
+    json_response!(StatusCode::OK, progress)
+}
+
+// Sends the current progress straight away, then again every time it changes
+#[axum::debug_handler]
+pub async fn import_status_events(
+    State(state): State<AppState>,
+    Extension(user): Extension<DBUser>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    // Subscribe first so nothing is missed between reading the progress and listening
+    let updates = BroadcastStream::new(state.importer.subscribe());
+    let current = state
+        .importer
+        .progress(user.id.clone())
+        .await
+        .unwrap_or_default();
+
+    // Updates dropped for lagging behind are fine, every event has the full totals
+    let updates = updates.filter_map(move |update| match update {
+        Ok(update) if update.user_id == user.id => Some(update.progress),
+        _ => None,
+    });
+
+    let stream = tokio_stream::once(current).chain(updates).map(|progress| {
+        Ok(Event::default()
+            .event("progress")
+            .data(json!(progress).to_string()))
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+// Furthest ahead the schedule can be requested
+const MAX_SCHEDULE_DAYS: u32 = 28;
+
+#[derive(Deserialize)]
+pub struct ScheduleQuery {
+    // IANA name of the users timezone, eg. Europe/London
+    timezone: Option<String>,
+    days: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct ScheduleDay {
+    // In the users timezone
+    date: NaiveDate,
+    episodes: Vec<DBScheduledEpisode>,
+}
+
+// When `date` starts in `timezone`. Midnight is skipped on some
+// daylight saving changes, the day then starts at the first hour that exists
+fn start_of_day(date: NaiveDate, timezone: Tz) -> DateTime<Utc> {
+    (0..24)
+        .find_map(|hour| {
+            date.and_hms_opt(hour, 0, 0)?
+                .and_local_timezone(timezone)
+                .earliest()
+        })
+        .map(|start| start.with_timezone(&Utc))
+        .unwrap_or_else(|| date.and_hms_opt(0, 0, 0).unwrap().and_utc())
+}
+
+// Episodes airing over the next `days` days (a week by default), starting at
+// the beginning of today in the users timezone and grouped by the day they air on
+#[axum::debug_handler]
+pub async fn get_schedule(
+    State(state): State<AppState>,
+    Extension(user): Extension<DBUser>,
+    Query(query): Query<ScheduleQuery>,
+) -> impl IntoResponse {
+    let Ok(timezone) = query.timezone.as_deref().unwrap_or("UTC").parse::<Tz>() else {
+        return json_response!(StatusCode::BAD_REQUEST, {
+            "error": "timezone must be an IANA timezone name, eg. Europe/London"
+        });
+    };
+    let days = query.days.unwrap_or(7).clamp(1, MAX_SCHEDULE_DAYS);
+
+    // Days are not always 24 hours long, so the end is found the same way as the start
+    let today = Utc::now().with_timezone(&timezone).date_naive();
+    let from = start_of_day(today, timezone);
+    let to = start_of_day(today + Duration::days(days as i64), timezone);
+
+    let episodes = match get_user_schedule(&state.db, &user.id, from, to).await {
+        Ok(episodes) => episodes,
+        Err(err) => {
+            tracing::error!("Failed to get schedule for user {}: {:?}", user.id, err);
+            return json_response!(StatusCode::INTERNAL_SERVER_ERROR, {
+                "error": "Failed to get schedule"
+            });
+        }
+    };
+
+    let mut schedule: Vec<ScheduleDay> = (0..days)
+        .map(|day| ScheduleDay {
+            date: today + Duration::days(day as i64),
+            episodes: vec![],
+        })
+        .collect();
+    for episode in episodes {
+        let date = episode.airing_at.with_timezone(&timezone).date_naive();
+        let day = (date - today).num_days() as usize;
+        if let Some(day) = schedule.get_mut(day) {
+            day.episodes.push(episode);
+        }
+    }
+
+    json_response!(StatusCode::OK, {
+        "timezone": timezone.name(),
+        "days": schedule
+    })
+}
@@ -0,0 +1,241 @@
+// Groups animes into series using the PREQUEL/SEQUEL relations
+// The series id is the first anime in watch order, this is
+// normally season one but can be a prequel movie or similar
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use sqlx::{MySql, Pool};
+
+use crate::models::anime::get_existing_anime_ids;
+use crate::models::anime_relations::get_sequel_relations;
+use crate::models::anime_series::{replace_anime_series, InsertAnimeSeries};
+
+// Stops a broken relation graph from walking forever
+const MAX_SERIES_WALK_DEPTH: usize = 50;
+
+// Rebuilds the series for every franchise `anime_ids` are part of
+pub async fn update_series(db: &Pool<MySql>, anime_ids: &[u32]) -> Result<(), anyhow::Error> {
+    if anime_ids.is_empty() {
+        return Ok(());
+    }
+
+    let mut nodes: HashSet<u32> = anime_ids.iter().cloned().collect();
+    let mut edges: HashSet<(u32, u32, String)> = HashSet::new();
+    let mut frontier: Vec<u32> = anime_ids.to_vec();
+
+    for _ in 0..MAX_SERIES_WALK_DEPTH {
+        if frontier.is_empty() {
+            break;
+        }
+
+        let relations = get_sequel_relations(db, &frontier).await?;
+        frontier = vec![];
+
+        for (anime_id, relation_id, relation) in relations {
+            let (anime_id, relation_id) = (anime_id as u32, relation_id as u32);
+            for id in [anime_id, relation_id] {
+                if nodes.insert(id) {
+                    frontier.push(id);
+                }
+            }
+            edges.insert((anime_id, relation_id, relation));
+        }
+    }
+
+    let nodes: Vec<u32> = nodes.into_iter().collect();
+    let imported: HashSet<u32> = get_existing_anime_ids(db, &nodes)
+        .await?
+        .into_iter()
+        .map(|id| id as u32)
+        .collect();
+
+    let edges: Vec<(u32, u32, String)> = edges.into_iter().collect();
+    let series = compute_series(&nodes, &edges, &imported);
+
+    replace_anime_series(db, &nodes, series).await
+}
+
+// Orders each connected group of animes so that prequels come before sequels
+// Only animes in `imported` are included, since anime_series references animes
+pub fn compute_series(
+    nodes: &[u32],
+    edges: &[(u32, u32, String)],
+    imported: &HashSet<u32>,
+) -> Vec<InsertAnimeSeries> {
+    // anime id: animes that are watched directly after it
+    let mut next: HashMap<u32, HashSet<u32>> = HashMap::new();
+    let mut neighbours: HashMap<u32, HashSet<u32>> = HashMap::new();
+
+    for (anime_id, relation_id, relation) in edges {
+        let (before, after) = match relation.as_str() {
+            "SEQUEL" => (*anime_id, *relation_id),
+            "PREQUEL" => (*relation_id, *anime_id),
+            _ => continue,
+        };
+
+        next.entry(before).or_default().insert(after);
+        neighbours.entry(before).or_default().insert(after);
+        neighbours.entry(after).or_default().insert(before);
+    }
+
+    let mut all_nodes: Vec<u32> = nodes.to_vec();
+    all_nodes.extend(neighbours.keys());
+    all_nodes.sort();
+    all_nodes.dedup();
+
+    let mut seen = HashSet::new();
+    let mut series = vec![];
+
+    for start in all_nodes {
+        if !seen.insert(start) {
+            continue;
+        }
+
+        // Everything connected to start
+        let mut group = vec![start];
+        let mut stack = vec![start];
+        while let Some(id) = stack.pop() {
+            for neighbour in neighbours.get(&id).into_iter().flatten() {
+                if seen.insert(*neighbour) {
+                    group.push(*neighbour);
+                    stack.push(*neighbour);
+                }
+            }
+        }
+
+        let ordered: Vec<u32> = watch_order(&group, &next)
+            .into_iter()
+            .filter(|id| imported.contains(id))
+            .collect();
+
+        let Some(&series_id) = ordered.first() else {
+            continue;
+        };
+
+        series.extend(
+            ordered
+                .into_iter()
+                .enumerate()
+                .map(|(i, anime_id)| InsertAnimeSeries {
+                    series_id,
+                    anime_id,
+                    series_order: i as u32 + 1,
+                }),
+        );
+    }
+
+    series
+}
+
+// Topological order of `group`, ties go to the lower (older) MAL id
+// Animes stuck in a relation cycle are added at the end
+fn watch_order(group: &[u32], next: &HashMap<u32, HashSet<u32>>) -> Vec<u32> {
+    let mut incoming: HashMap<u32, usize> = group.iter().map(|id| (*id, 0)).collect();
+    for id in group {
+        for after in next.get(id).into_iter().flatten() {
+            *incoming.entry(*after).or_default() += 1;
+        }
+    }
+
+    let mut ready: BinaryHeap<Reverse<u32>> = incoming
+        .iter()
+        .filter(|(_, count)| **count == 0)
+        .map(|(id, _)| Reverse(*id))
+        .collect();
+
+    let mut ordered = vec![];
+    while let Some(Reverse(id)) = ready.pop() {
+        ordered.push(id);
+        for after in next.get(&id).into_iter().flatten() {
+            let count = incoming.get_mut(after).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                ready.push(Reverse(*after));
+            }
+        }
+    }
+
+    let placed: HashSet<u32> = ordered.iter().cloned().collect();
+    let mut remaining: Vec<u32> = group
+        .iter()
+        .filter(|id| !placed.contains(id))
+        .cloned()
+        .collect();
+    remaining.sort();
+    ordered.extend(remaining);
+
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edge(anime_id: u32, relation_id: u32, relation: &str) -> (u32, u32, String) {
+        (anime_id, relation_id, relation.to_string())
+    }
+
+    #[test]
+    fn compute_series_orders_by_relations() {
+        // name, edges, imported animes, expected (series_id, anime_id, series_order)
+        let cases = [
+            (
+                "linear chain",
+                vec![
+                    edge(1, 2, "SEQUEL"),
+                    edge(2, 3, "SEQUEL"),
+                    edge(3, 2, "PREQUEL"),
+                ],
+                vec![1, 2, 3],
+                vec![(1, 1, 1), (1, 2, 2), (1, 3, 3)],
+            ),
+            (
+                "prequel movie with a higher id",
+                vec![edge(5, 10, "PREQUEL"), edge(5, 6, "SEQUEL")],
+                vec![5, 6, 10],
+                vec![(10, 10, 1), (10, 5, 2), (10, 6, 3)],
+            ),
+            (
+                "sequels of the same anime go by id",
+                vec![edge(1, 3, "SEQUEL"), edge(1, 2, "SEQUEL")],
+                vec![1, 2, 3],
+                vec![(1, 1, 1), (1, 2, 2), (1, 3, 3)],
+            ),
+            (
+                "cycle is added at the end",
+                vec![
+                    edge(1, 2, "SEQUEL"),
+                    edge(2, 3, "SEQUEL"),
+                    edge(3, 2, "SEQUEL"),
+                ],
+                vec![1, 2, 3],
+                vec![(1, 1, 1), (1, 2, 2), (1, 3, 3)],
+            ),
+            (
+                "missing middle season still joins the series",
+                vec![edge(1, 2, "SEQUEL"), edge(2, 3, "SEQUEL")],
+                vec![1, 3],
+                vec![(1, 1, 1), (1, 3, 2)],
+            ),
+            (
+                "other relations do not join series",
+                vec![edge(1, 2, "SIDE_STORY"), edge(3, 4, "SEQUEL")],
+                vec![1, 2, 3, 4],
+                vec![(1, 1, 1), (2, 2, 1), (3, 3, 1), (3, 4, 2)],
+            ),
+        ];
+
+        for (name, edges, imported, expected) in cases {
+            let nodes: Vec<u32> = imported.clone();
+            let imported: HashSet<u32> = imported.into_iter().collect();
+
+            let series: Vec<(u32, u32, u32)> = compute_series(&nodes, &edges, &imported)
+                .into_iter()
+                .map(|series| (series.series_id, series.anime_id, series.series_order))
+                .collect();
+
+            assert_eq!(series, expected, "{}", name);
+        }
+    }
+}
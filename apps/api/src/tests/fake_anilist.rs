@@ -0,0 +1,126 @@
+// Stands in for graphql.anilist.co, answering the media queries
+// made by `generate_gql_query` from a fixed set of animes
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::routing::post;
+use axum::{Json, Router};
+use serde_json::{json, Value};
+
+use super::spawn_server;
+use crate::anilist::api_types::{
+    AniListAnimeItem, CoverImage, FuzzyDate, Relations, RelationsEdge, RelationsNode, StudioEdge,
+    StudioNode, Studios, Title,
+};
+use crate::anilist::GqlQuery;
+
+#[derive(Default)]
+pub struct FakeAniList {
+    // MAL id: anime
+    animes: HashMap<u32, AniListAnimeItem>,
+    pub requests: AtomicUsize,
+}
+
+impl FakeAniList {
+    pub fn new(animes: Vec<AniListAnimeItem>) -> Self {
+        FakeAniList {
+            animes: animes
+                .into_iter()
+                .filter_map(|anime| Some((anime.id_mal?, anime)))
+                .collect(),
+            requests: AtomicUsize::new(0),
+        }
+    }
+
+    // Starts the server, returning its url and the fake to inspect requests
+    pub async fn spawn(self) -> (String, Arc<FakeAniList>) {
+        let fake = Arc::new(self);
+        let router = Router::new()
+            .route("/", post(handle_query))
+            .with_state(fake.clone());
+
+        (spawn_server(router).await, fake)
+    }
+}
+
+// A finished anime with the given relations, as (MAL id, relation type)
+pub fn anime(id_mal: u32, title: &str, relations: &[(u32, &str)]) -> AniListAnimeItem {
+    AniListAnimeItem {
+        status: "FINISHED".to_string(),
+        relations: Some(Relations {
+            edges: relations
+                .iter()
+                .map(|(id, relation_type)| RelationsEdge {
+                    relation_type: relation_type.to_string(),
+                    node: RelationsNode {
+                        id_mal: Some(*id as i32),
+                        media_type: Some("ANIME".to_string()),
+                    },
+                })
+                .collect(),
+        }),
+        title: Title {
+            romaji: Some(title.to_string()),
+            english: Some(title.to_string()),
+            native: None,
+        },
+        id_mal: Some(id_mal),
+        format: Some("TV".to_string()),
+        season: Some("SPRING".to_string()),
+        season_year: Some(2020),
+        start_date: Some(FuzzyDate {
+            year: Some(2020),
+            month: Some(4),
+            day: Some(1),
+        }),
+        episodes: Some(12),
+        duration: Some(24),
+        genres: Some(vec!["Action".to_string()]),
+        studios: Some(Studios {
+            edges: vec![StudioEdge {
+                is_main: true,
+                node: StudioNode {
+                    name: "Studio".to_string(),
+                },
+            }],
+        }),
+        cover_image: Some(CoverImage {
+            large: Some(format!("https://example.com/{}.jpg", id_mal)),
+        }),
+        ..Default::default()
+    }
+}
+
+async fn handle_query(
+    State(fake): State<Arc<FakeAniList>>,
+    Json(query): Json<GqlQuery>,
+) -> ([(&'static str, &'static str); 2], Json<Value>) {
+    fake.requests.fetch_add(1, Ordering::Relaxed);
+
+    let mut data = serde_json::Map::new();
+    let mut errors = vec![];
+    for (alias, id) in query.variables.as_object().into_iter().flatten() {
+        let anime = id
+            .as_u64()
+            .and_then(|id| fake.animes.get(&(id as u32)))
+            .cloned();
+
+        if anime.is_none() {
+            errors.push(json!({ "message": "Not Found.", "status": 404, "path": [alias] }));
+        }
+        data.insert(alias.clone(), json!(anime));
+    }
+
+    let mut body = json!({ "data": data });
+    if !errors.is_empty() {
+        body["errors"] = json!(errors);
+    }
+
+    (
+        [("x-ratelimit-limit", "90"), ("x-ratelimit-remaining", "89")],
+        Json(body),
+    )
+}
@@ -0,0 +1,165 @@
+// Stands in for the MAL REST api and its OAuth endpoints. The REST api
+// is served under /v2 and OAuth under /v1/oauth2, like the real thing
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use axum::extract::{Path, Query, State};
+use axum::http::header::{AUTHORIZATION, HOST};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::Redirect;
+use axum::routing::{get, patch, post};
+use axum::{Form, Json, Router};
+use serde_json::{json, Value};
+
+use super::spawn_server;
+
+// MAL id and form of a list status update
+pub type ListUpdate = (u32, HashMap<String, String>);
+
+#[derive(Default)]
+pub struct FakeMal {
+    pub user_id: i32,
+    pub user_name: String,
+    // MAL id, title, list status
+    pub list: Vec<(u32, String, String)>,
+    // Most entries returned per page, the requested limit when not set
+    pub page_size: Option<usize>,
+    // Requests for the page starting at this offset fail
+    pub failing_offset: Option<usize>,
+    // Access token that gets a 401, as if it had expired
+    pub expired_token: Option<String>,
+    // Every list status update, anime not on `list` get a 404
+    pub list_updates: Arc<Mutex<Vec<ListUpdate>>>,
+}
+
+impl FakeMal {
+    // Starts the server, returning its url
+    pub async fn spawn(self) -> String {
+        let router = Router::new()
+            .route("/v1/oauth2/authorize", get(authorize))
+            .route("/v1/oauth2/token", post(token))
+            .route("/v2/users/:user", get(user))
+            .route("/v2/users/:user/animelist", get(anime_list))
+            .route("/v2/anime/:id/my_list_status", patch(update_list_status))
+            .with_state(Arc::new(self));
+
+        spawn_server(router).await
+    }
+}
+
+// Logs straight in, sending the browser back with a code
+async fn authorize(Query(query): Query<HashMap<String, String>>) -> Redirect {
+    let redirect_uri = query.get("redirect_uri").cloned().unwrap_or_default();
+    let state = query.get("state").cloned().unwrap_or_default();
+
+    Redirect::temporary(&format!("{}?code=test-code&state={}", redirect_uri, state))
+}
+
+async fn token() -> Json<Value> {
+    Json(json!({
+        "access_token": "test-access-token",
+        "token_type": "Bearer",
+        "expires_in": 2678400,
+        "refresh_token": "test-refresh-token"
+    }))
+}
+
+async fn user(State(fake): State<Arc<FakeMal>>, Path(_user): Path<String>) -> Json<Value> {
+    Json(json!({
+        "id": fake.user_id,
+        "name": fake.user_name,
+        "picture": "https://example.com/user.jpg"
+    }))
+}
+
+async fn anime_list(
+    State(fake): State<Arc<FakeMal>>,
+    Path(user): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Result<Json<Value>, StatusCode> {
+    let offset: usize = query
+        .get("offset")
+        .and_then(|offset| offset.parse().ok())
+        .unwrap_or(0);
+    let limit: usize = query
+        .get("limit")
+        .and_then(|limit| limit.parse().ok())
+        .unwrap_or(100);
+    let limit = fake.page_size.map_or(limit, |size| size.min(limit));
+
+    let bearer = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if bearer.is_some() && bearer == fake.expired_token.as_deref() {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    if fake.failing_offset == Some(offset) {
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    let data: Vec<Value> = fake
+        .list
+        .iter()
+        .skip(offset)
+        .take(limit)
+        .map(|(id, title, status)| {
+            json!({
+                "node": {
+                    "id": id,
+                    "title": title,
+                    "main_picture": {
+                        "large": format!("https://example.com/{}l.jpg", id),
+                        "medium": format!("https://example.com/{}.jpg", id)
+                    },
+                    "status": "finished_airing",
+                    "broadcast": null
+                },
+                "list_status": {
+                    "status": status,
+                    "score": 0,
+                    "num_episodes_watched": id,
+                    "is_rewatching": false,
+                    // Only a year and month, as MAL allows
+                    "start_date": "2024-01",
+                    "finish_date": "2024-02-03",
+                    "updated_at": "2024-02-03T12:30:00+09:00"
+                }
+            })
+        })
+        .collect();
+
+    let mut paging = json!({});
+    if offset + limit < fake.list.len() {
+        let host = headers
+            .get(HOST)
+            .and_then(|host| host.to_str().ok())
+            .unwrap_or_default();
+        paging["next"] = json!(format!(
+            "http://{}/v2/users/{}/animelist?offset={}&limit={}",
+            host,
+            user,
+            offset + limit,
+            limit
+        ));
+    }
+
+    Ok(Json(json!({ "data": data, "paging": paging })))
+}
+
+async fn update_list_status(
+    State(fake): State<Arc<FakeMal>>,
+    Path(id): Path<u32>,
+    Form(form): Form<HashMap<String, String>>,
+) -> Result<Json<Value>, StatusCode> {
+    if !fake.list.iter().any(|(list_id, _, _)| *list_id == id) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    fake.list_updates.lock().unwrap().push((id, form.clone()));
+
+    Ok(Json(json!(form)))
+}
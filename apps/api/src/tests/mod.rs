@@ -0,0 +1,534 @@
+// End to end tests against in-process fakes of AniList and MAL
+// Tests that need a database are ignored by default, run them with
+// `cargo test -- --include-ignored` and TEST_DATABASE_URL pointing
+// at a MySQL database with the prisma schema pushed to it
+
+mod fake_anilist;
+mod fake_mal;
+
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::Router;
+use axum_extra::extract::cookie::Key;
+use chrono::{NaiveDate, Utc};
+use reqwest::header::{COOKIE, LOCATION, SET_COOKIE};
+use reqwest::redirect::Policy;
+use reqwest::{Client, Response};
+use serde_json::Value;
+use sqlx::mysql::MySqlPoolOptions;
+use sqlx::{MySql, Pool};
+use tokio::net::TcpListener;
+
+use self::fake_anilist::{anime, FakeAniList};
+use self::fake_mal::FakeMal;
+use crate::anilist::provider::AniListProvider;
+use crate::auth::oauth::create_oauth_client;
+use crate::config::UpstreamUrls;
+use crate::importer::{AnimeWatchStatus, CrawlSettings, Importer, RefreshSettings};
+use crate::jikan::JikanProvider;
+use crate::mal::token::with_mal_token;
+use crate::mal::{get_mal_user_list, update_mal_list_status, MalListStatusUpdate, MalRejected};
+use crate::metadata::{FallbackProvider, MetadataProvider};
+use crate::models::user::DBUser;
+use crate::{create_router, AppState};
+
+// Serves `router` on a random local port, returning its url
+pub async fn spawn_server(router: Router) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, router.into_make_service())
+            .await
+            .unwrap();
+    });
+
+    format!("http://{}", address)
+}
+
+async fn test_db() -> Pool<MySql> {
+    let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set");
+    MySqlPoolOptions::new()
+        .max_connections(5)
+        .connect(&url)
+        .await
+        .expect("Failed to connect to test database")
+}
+
+fn urls(anilist: &str, mal: &str) -> UpstreamUrls {
+    UpstreamUrls {
+        anilist: anilist.to_string(),
+        mal_api: format!("{}/v2", mal),
+        mal_oauth: format!("{}/v1/oauth2", mal),
+        // Nothing should fall back to jikan, this fails straight away if it does
+        jikan: "http://127.0.0.1:1".to_string(),
+    }
+}
+
+// The api with its importer, served on a random local port
+struct TestApp {
+    url: String,
+    client: Client,
+    // name: value of every cookie the api has set
+    cookies: HashMap<String, String>,
+}
+
+impl TestApp {
+    async fn spawn(db: Pool<MySql>, urls: UpstreamUrls) -> TestApp {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let reqwest = Client::new();
+        let provider = FallbackProvider::new(
+            AniListProvider::new(reqwest.clone(), urls.anilist.clone(), 10),
+            JikanProvider::new(reqwest.clone(), urls.jikan.clone()),
+        );
+        let importer = Importer::new(Arc::new(provider), db.clone(), CrawlSettings::default());
+        let state = AppState {
+            key: Key::generate(),
+            db,
+            reqwest,
+            importer: importer.spawn(),
+            admin_mal_ids: vec![],
+            urls: urls.clone(),
+            api_url: url.clone(),
+            refresh: RefreshSettings::default(),
+        };
+        let oauth_client = create_oauth_client(
+            url.clone(),
+            urls.mal_oauth.clone(),
+            "client-id".to_string(),
+            "client-secret".to_string(),
+        );
+
+        let router = create_router(state, oauth_client);
+        tokio::spawn(async move {
+            axum::serve(listener, router.into_make_service())
+                .await
+                .unwrap();
+        });
+
+        TestApp {
+            url,
+            client: Client::builder().redirect(Policy::none()).build().unwrap(),
+            cookies: HashMap::new(),
+        }
+    }
+
+    // Requests `url` with the stored cookies, storing any new ones
+    async fn get(&mut self, url: &str) -> Response {
+        let cookies = self
+            .cookies
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        let res = self
+            .client
+            .get(url)
+            .header(COOKIE, cookies)
+            .send()
+            .await
+            .unwrap();
+
+        for cookie in res.headers().get_all(SET_COOKIE) {
+            let cookie = cookie.to_str().unwrap();
+            let pair = cookie.split(';').next().unwrap();
+            if let Some((name, value)) = pair.split_once('=') {
+                self.cookies.insert(name.to_string(), value.to_string());
+            }
+        }
+
+        res
+    }
+
+    async fn get_json(&mut self, path: &str) -> Value {
+        let url = format!("{}{}", self.url, path);
+        let res = self.get(&url).await;
+        assert!(
+            res.status().is_success(),
+            "{} returned {}",
+            path,
+            res.status()
+        );
+
+        res.json().await.unwrap()
+    }
+
+    // Goes through the MAL login flow the same way the browser popup does
+    async fn login(&mut self) {
+        let redirect = format!("{}/oauth/mal/redirect", self.url);
+        let res = self.get(&redirect).await;
+        let authorize = location(&res);
+
+        let res = self.get(&authorize).await;
+        let callback = location(&res);
+
+        let res = self.get(&callback).await;
+        assert!(
+            res.status().is_success(),
+            "Callback returned {}",
+            res.status()
+        );
+        assert!(self.cookies.contains_key("token"), "No session cookie set");
+    }
+
+    // Polls the import status until nothing for the user is queued
+    async fn wait_for_import(&mut self) -> Value {
+        for _ in 0..100 {
+            let status = self.get_json("/api/v1/user/import/status").await;
+            if status["queued"] == 0 && status["done"] != 0 {
+                return status;
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+
+        panic!("Import did not finish");
+    }
+}
+
+fn test_user() -> DBUser {
+    let now = Utc::now().naive_utc();
+    DBUser {
+        id: "user".to_string(),
+        name: "tester".to_string(),
+        picture: "".to_string(),
+        mal_id: 1,
+        mal_access_token: "token".to_string(),
+        mal_refresh_token: "".to_string(),
+        mal_token_expires_at: None,
+        mal_relogin_required: false,
+        list_last_update: now,
+        created_at: now,
+        updated_at: now,
+        deleted_at: None,
+    }
+}
+
+fn location(res: &Response) -> String {
+    res.headers()
+        .get(LOCATION)
+        .expect("Expected a redirect")
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+#[tokio::test]
+async fn anilist_provider_reports_missing_animes() {
+    let (url, fake) = FakeAniList::new(vec![anime(1, "One", &[(2, "SEQUEL")])])
+        .spawn()
+        .await;
+    let provider = AniListProvider::new(Client::new(), url, 10);
+
+    let batch = provider.fetch(vec![1, 404]).await.unwrap();
+
+    assert_eq!(fake.requests.load(Ordering::Relaxed), 1);
+    assert_eq!(batch.animes.len(), 1);
+    assert_eq!(batch.animes[0].id_mal, 1);
+    assert_eq!(batch.animes[0].relations[0].id_mal, 2);
+    assert_eq!(batch.animes[0].format.as_deref(), Some("TV"));
+    assert_eq!(
+        batch.animes[0].start_date,
+        NaiveDate::from_ymd_opt(2020, 4, 1)
+    );
+    assert_eq!(batch.animes[0].genres, vec!["Action"]);
+    assert!(batch.animes[0].studios[0].is_main);
+    assert_eq!(batch.not_found, vec![404]);
+}
+
+#[tokio::test]
+async fn failed_fallback_does_not_report_missing_animes() {
+    let (url, _) = FakeAniList::new(vec![anime(1, "One", &[])]).spawn().await;
+    let provider = FallbackProvider::new(
+        AniListProvider::new(Client::new(), url, 10),
+        JikanProvider::new(Client::new(), "http://127.0.0.1:1".to_string()),
+    );
+
+    let batch = provider.fetch(vec![1, 404]).await.unwrap();
+
+    assert_eq!(batch.animes.len(), 1);
+    assert!(batch.not_found.is_empty());
+}
+
+#[tokio::test]
+async fn mal_list_is_read_from_configured_url() {
+    let url = FakeMal {
+        user_id: 1,
+        user_name: "tester".to_string(),
+        list: vec![(1, "One".to_string(), "watching".to_string())],
+        ..Default::default()
+    }
+    .spawn()
+    .await;
+
+    let list = get_mal_user_list(
+        Client::new(),
+        &format!("{}/v2", url),
+        test_user().mal_access_token,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(list.data.len(), 1);
+    assert_eq!(list.data[0].node.id, 1);
+    assert_eq!(list.data[0].list_status.status, "watching");
+}
+
+#[tokio::test]
+async fn mal_list_status_is_read_in_full() {
+    let url = FakeMal {
+        list: numbered_list(3),
+        ..Default::default()
+    }
+    .spawn()
+    .await;
+
+    let list = get_mal_user_list(
+        Client::new(),
+        &format!("{}/v2", url),
+        test_user().mal_access_token,
+    )
+    .await
+    .unwrap();
+
+    let entry = list.data[2].user_entry("user");
+    assert_eq!(entry.anime_id, 3);
+    assert_eq!(entry.list_status.num_episodes_watched, 3);
+    assert_eq!(entry.list_status.start_date, None);
+    assert_eq!(
+        entry.list_status.finish_date,
+        NaiveDate::from_ymd_opt(2024, 2, 3)
+    );
+    assert_eq!(
+        entry.list_status.updated_at,
+        NaiveDate::from_ymd_opt(2024, 2, 3)
+            .unwrap()
+            .and_hms_opt(3, 30, 0)
+    );
+}
+
+// A list of `len` animes with ids counting up from 1
+fn numbered_list(len: u32) -> Vec<(u32, String, String)> {
+    (1..=len)
+        .map(|id| (id, format!("Anime {}", id), "watching".to_string()))
+        .collect()
+}
+
+#[tokio::test]
+async fn mal_list_follows_next_page() {
+    let url = FakeMal {
+        list: numbered_list(5),
+        page_size: Some(2),
+        ..Default::default()
+    }
+    .spawn()
+    .await;
+
+    let list = get_mal_user_list(
+        Client::new(),
+        &format!("{}/v2", url),
+        test_user().mal_access_token,
+    )
+    .await
+    .unwrap();
+
+    assert!(list.error.is_none());
+    let ids: Vec<u32> = list.data.iter().map(|item| item.node.id).collect();
+    assert_eq!(ids, vec![1, 2, 3, 4, 5]);
+}
+
+#[tokio::test]
+async fn mal_list_keeps_pages_before_failure() {
+    let url = FakeMal {
+        list: numbered_list(5),
+        page_size: Some(2),
+        failing_offset: Some(2),
+        ..Default::default()
+    }
+    .spawn()
+    .await;
+
+    let list = get_mal_user_list(
+        Client::new(),
+        &format!("{}/v2", url),
+        test_user().mal_access_token,
+    )
+    .await
+    .unwrap();
+
+    assert!(list.error.is_some());
+    let ids: Vec<u32> = list.data.iter().map(|item| item.node.id).collect();
+    assert_eq!(ids, vec![1, 2]);
+}
+
+#[tokio::test]
+async fn mal_list_status_update_is_sent() {
+    let mal = FakeMal {
+        list: numbered_list(1),
+        ..Default::default()
+    };
+    let list_updates = mal.list_updates.clone();
+    let mal_api = format!("{}/v2", mal.spawn().await);
+
+    let update = MalListStatusUpdate {
+        status: Some(AnimeWatchStatus::Completed),
+        score: Some(8),
+        num_episodes_watched: None,
+    };
+    update_mal_list_status(
+        Client::new(),
+        &mal_api,
+        test_user().mal_access_token,
+        1,
+        &update,
+    )
+    .await
+    .unwrap();
+
+    let list_updates = list_updates.lock().unwrap().clone();
+    assert_eq!(list_updates.len(), 1);
+    let (id, form) = &list_updates[0];
+    assert_eq!(*id, 1);
+    assert_eq!(form.get("status").map(String::as_str), Some("completed"));
+    assert_eq!(form.get("score").map(String::as_str), Some("8"));
+    assert!(!form.contains_key("num_watched_episodes"));
+}
+
+#[tokio::test]
+async fn mal_list_status_update_of_unknown_anime_is_rejected() {
+    let mal_api = format!("{}/v2", FakeMal::default().spawn().await);
+
+    let err = update_mal_list_status(
+        Client::new(),
+        &mal_api,
+        test_user().mal_access_token,
+        1,
+        &MalListStatusUpdate::default(),
+    )
+    .await
+    .unwrap_err();
+
+    assert!(err.is::<MalRejected>());
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn expired_mal_token_is_refreshed() {
+    let db = test_db().await;
+
+    let mal = FakeMal {
+        list: numbered_list(1),
+        expired_token: Some("expired".to_string()),
+        ..Default::default()
+    }
+    .spawn()
+    .await;
+    let oauth_client = create_oauth_client(
+        "http://127.0.0.1".to_string(),
+        format!("{}/v1/oauth2", mal),
+        "client-id".to_string(),
+        "client-secret".to_string(),
+    );
+    let mal_api = format!("{}/v2", mal);
+    let mut user = test_user();
+    user.mal_access_token = "expired".to_string();
+    user.mal_refresh_token = "refresh".to_string();
+
+    let list = with_mal_token(&db, &oauth_client, &mut user, |token| {
+        get_mal_user_list(Client::new(), &mal_api, token)
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(list.data.len(), 1);
+    assert_eq!(user.mal_access_token, "test-access-token");
+    assert_eq!(user.mal_refresh_token, "test-refresh-token");
+    assert!(user.mal_token_expires_at.is_some());
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn login_imports_list() {
+    let db = test_db().await;
+
+    // Ids are high so they do not clash with real animes in a shared database
+    let (anilist, _) = FakeAniList::new(vec![
+        anime(900001, "Season One", &[(900002, "SEQUEL")]),
+        anime(900002, "Season Two", &[(900001, "PREQUEL")]),
+    ])
+    .spawn()
+    .await;
+    let mal = FakeMal {
+        user_id: 900001,
+        user_name: "tester".to_string(),
+        list: vec![(900001, "Season One".to_string(), "completed".to_string())],
+        ..Default::default()
+    }
+    .spawn()
+    .await;
+
+    let mut app = TestApp::spawn(db, urls(&anilist, &mal)).await;
+    app.login().await;
+
+    let status = app.wait_for_import().await;
+    assert_eq!(status["failed"], 0);
+
+    let user = app.get_json("/api/v1/auth/me").await;
+    assert_eq!(user["name"], "tester");
+
+    let list = app.get_json("/api/v1/user/list").await;
+    let ids: Vec<u64> = list["animes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|anime| anime["id"].as_u64().unwrap())
+        .collect();
+    assert_eq!(ids, vec![900001]);
+    assert_eq!(list["animes"][0]["episodes"], 12);
+    assert_eq!(list["animes"][0]["genres"][0], "Action");
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn sync_removes_entries_missing_from_mal() {
+    let db = test_db().await;
+
+    let (anilist, _) = FakeAniList::new(vec![
+        anime(900011, "Kept", &[]),
+        anime(900012, "Removed", &[]),
+    ])
+    .spawn()
+    .await;
+    let mal_list = |ids: &[u32]| FakeMal {
+        user_id: 900011,
+        user_name: "syncer".to_string(),
+        list: ids
+            .iter()
+            .map(|id| (*id, format!("Anime {}", id), "watching".to_string()))
+            .collect(),
+        ..Default::default()
+    };
+
+    let mal = mal_list(&[900011, 900012]).spawn().await;
+    let mut app = TestApp::spawn(db.clone(), urls(&anilist, &mal)).await;
+    app.login().await;
+    app.wait_for_import().await;
+
+    // Logging in again syncs the list, which no longer has 900012 on MAL
+    let mal = mal_list(&[900011]).spawn().await;
+    let mut app = TestApp::spawn(db, urls(&anilist, &mal)).await;
+    app.login().await;
+
+    let list = app.get_json("/api/v1/user/list").await;
+    let ids: Vec<u64> = list["list_entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| entry["anime_id"].as_u64().unwrap())
+        .collect();
+    assert_eq!(ids, vec![900011]);
+}
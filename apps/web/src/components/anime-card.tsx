@@ -2,6 +2,7 @@ import { useDragDropContext } from "@thisbeyond/solid-dnd";
 import { createSortable } from "@thisbeyond/solid-dnd";
 import { createSignal } from "solid-js";
 import { Anime } from "~/hooks/useAnimeList";
+import { createUpdateListEntry } from "~/hooks/createUpdateListEntry";
 
 import {
   ContextMenu,
@@ -62,7 +63,8 @@ export const DraggableAnimeCard = (
 ) => {
   const sortable = createSortable(props.anime.id);
   const [state] = useDragDropContext();
-  const [watchStatus, _setWatchStatus] = createSignal("WATCHING");
+  const [watchStatus, setWatchStatus] = createSignal("WATCHING");
+  const updateListEntry = createUpdateListEntry();
 
   return (
     <div
@@ -90,8 +92,12 @@ export const DraggableAnimeCard = (
                   <ContextMenuGroup>
                     <ContextMenuRadioGroup
                       value={watchStatus()}
-                      onChange={(_state) => {
-                        // setWatchStatus(state);
+                      onChange={(state) => {
+                        setWatchStatus(state);
+                        updateListEntry.mutate({
+                          anime_id: props.anime.id,
+                          status: state.toLowerCase(),
+                        });
                       }}
                     >
                       <ContextMenuRadioItem value="WATCHING">
@@ -36,7 +36,8 @@ const AuthProvider = (props: { children: JSX.Element }) => {
   const nav = useNavigate();
 
   createEffect(() => {
-    if (user.error) {
+    // MAL access was lost, logging in again gets a new token
+    if (user.error || user.data?.mal_relogin_required) {
       nav("/login");
     }
   });
@@ -0,0 +1,41 @@
+import { createMutation, useQueryClient } from "@tanstack/solid-query";
+import { AnimeWatchStatus } from "./useAnimeList";
+
+export type ListEntryUpdate = {
+  anime_id: number;
+  status?: AnimeWatchStatus;
+  score?: number;
+  num_episodes_watched?: number;
+};
+
+export const createUpdateListEntry = () => {
+  const queryClient = useQueryClient();
+
+  return createMutation(() => ({
+    mutationKey: ["anime", "list", "entry", "update"],
+    mutationFn: async ({ anime_id, ...update }: ListEntryUpdate) => {
+      const res = await fetch(
+        `${import.meta.env.PUBLIC_API_URL ?? ""}/api/v1/user/list/${anime_id}`,
+        {
+          method: "PATCH",
+          credentials: "include",
+          body: JSON.stringify(update),
+          headers: {
+            "Content-Type": "application/json",
+          },
+        },
+      );
+
+      if (!res.ok) {
+        throw res;
+      }
+
+      return res;
+    },
+    onSettled: () => {
+      queryClient.invalidateQueries({
+        queryKey: ["anime", "list"],
+      });
+    },
+  }));
+};
@@ -1,5 +1,5 @@
-import { createQuery } from "@tanstack/solid-query";
-import { Accessor } from "solid-js";
+import { createQuery, useQueryClient } from "@tanstack/solid-query";
+import { Accessor, onCleanup } from "solid-js";
 
 export const AnimeReleaseStatus = {
   Finished: "FINISHED",
@@ -21,13 +21,49 @@ export const AnimeWatchStatus = {
 export type AnimeWatchStatus =
   (typeof AnimeWatchStatus)[keyof typeof AnimeWatchStatus];
 
+export type AnimeFormat =
+  | "TV"
+  | "TV_SHORT"
+  | "MOVIE"
+  | "SPECIAL"
+  | "OVA"
+  | "ONA"
+  | "MUSIC";
+
+export type AnimeTag = {
+  tag: string;
+  rank: number;
+  is_spoiler: boolean;
+};
+
+export type AnimeStudio = {
+  studio: string;
+  is_main: boolean;
+};
+
 export type Anime = {
   id: number;
   romaji_title: string;
+  english_title?: string;
+  native_title?: string;
+  synonyms: string[];
   status: AnimeReleaseStatus;
+  format?: AnimeFormat;
   picture: string;
+  banner_image?: string;
+  description?: string;
   season?: string;
   season_year?: number;
+  start_date?: string;
+  end_date?: string;
+  episodes?: number;
+  duration?: number;
+  genres: string[];
+  tags: AnimeTag[];
+  studios: AnimeStudio[];
+  average_score?: number;
+  popularity?: number;
+  is_adult: boolean;
   created_at: string;
   updated_at: string;
 };
@@ -36,6 +72,24 @@ export type ListEntry = {
   anime_id: number;
   watch_priority: number;
   watch_status: AnimeWatchStatus;
+  // 0 when not scored
+  score: number;
+  num_episodes_watched: number;
+  is_rewatching: boolean;
+  start_date?: string;
+  finish_date?: string;
+  // MAL's priority, 0 = low to 2 = high
+  mal_priority: number;
+  comments: string;
+  // When the entry last changed on MAL
+  mal_updated_at?: string;
+};
+
+export type ImportProgress = {
+  queued: number;
+  done: number;
+  failed: number;
+  ignored: number;
 };
 
 export const useAnimeList = ({
@@ -43,6 +97,23 @@ export const useAnimeList = ({
 }: {
   hasReordered: Accessor<boolean>;
 }) => {
+  const queryClient = useQueryClient();
+
+  // Refetch the list as the importer finishes animes in it
+  const events = new EventSource(
+    `${import.meta.env.PUBLIC_API_URL ?? ""}/api/v1/user/import/events`,
+    { withCredentials: true },
+  );
+  let lastDone = 0;
+  events.addEventListener("progress", (event) => {
+    const progress = JSON.parse(event.data) as ImportProgress;
+    if (progress.done !== lastDone) {
+      lastDone = progress.done;
+      queryClient.invalidateQueries({ queryKey: ["anime", "list"] });
+    }
+  });
+  onCleanup(() => events.close());
+
   return createQuery(() => ({
     enabled: !hasReordered(),
     staleTime: 1000 * 60 * 5,
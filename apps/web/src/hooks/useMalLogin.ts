@@ -19,7 +19,7 @@ export const useMalLogin = (props?: MalLoginProps) => {
     e?.preventDefault();
     setIsLoggingIn(true);
 
-    if (user.data) {
+    if (user.data && !user.data.mal_relogin_required) {
       nav(props.redirect);
       return;
     }
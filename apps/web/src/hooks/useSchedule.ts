@@ -0,0 +1,42 @@
+import { createQuery } from "@tanstack/solid-query";
+import { AnimeReleaseStatus, AnimeWatchStatus } from "./useAnimeList";
+
+export type ScheduledEpisode = {
+  anime_id: number;
+  episode: number;
+  airing_at: string;
+  romaji_title: string;
+  english_title?: string;
+  picture: string;
+  duration?: number;
+  anime_status: AnimeReleaseStatus;
+  watch_status: AnimeWatchStatus;
+};
+
+export type ScheduleDay = {
+  // YYYY-MM-DD in the browsers timezone
+  date: string;
+  episodes: ScheduledEpisode[];
+};
+
+export const useSchedule = () => {
+  return createQuery(() => ({
+    staleTime: 1000 * 60 * 5,
+    queryKey: ["schedule"],
+    queryFn: async () => {
+      const timezone = Intl.DateTimeFormat().resolvedOptions().timeZone;
+      const res = await fetch(
+        `${import.meta.env.PUBLIC_API_URL ?? ""}/api/v1/user/schedule?timezone=${encodeURIComponent(timezone)}`,
+        {
+          credentials: "include",
+        },
+      );
+
+      if (!res.ok) {
+        throw res;
+      }
+
+      return (await res.json()) as { timezone: string; days: ScheduleDay[] };
+    },
+  }));
+};